@@ -0,0 +1,23 @@
+//! Solves small endings and writes them as Syzygy tables, which is how the test
+//! fixtures in tests/syzygy are built (see generate.sh there).
+use std::env;
+use std::io;
+use std::path::Path;
+
+use chessengine::engine::syzygy::generate;
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let result = match args.as_slice() {
+        [dir, names @ ..] if !names.is_empty() => generate::generate(Path::new(dir), names),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "usage: gentb <dir> <table>...",
+        )),
+    };
+
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
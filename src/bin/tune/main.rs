@@ -0,0 +1,115 @@
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, BufReader};
+use std::time::Duration;
+
+use chessengine::engine::wdl::{Sample, WdlModel};
+
+mod selfplay;
+
+/* Offline tuning, kept out of the engine itself. */
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let result = match args.get(1).map(String::as_str) {
+        Some("selfplay") => self_play(&args[2..]),
+        Some("skillmatch") => skill_match(&args[2..]),
+        Some("fitwdl") => fit_wdl(args.get(2)),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "usage: tune selfplay|skillmatch|fitwdl <args>...",
+        )),
+    };
+
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+/// Plays self-play games and appends the samples `fitwdl` needs to a file.
+fn self_play(args: &[String]) -> io::Result<()> {
+    let usage = || {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "usage: tune selfplay <games> <movetime ms> <samples>",
+        )
+    };
+    let (games, move_time, path) = match args {
+        [games, move_time, path] => (
+            games.parse::<usize>().map_err(|_| usage())?,
+            move_time.parse::<u64>().map_err(|_| usage())?,
+            path,
+        ),
+        _ => return Err(usage()),
+    };
+
+    let mut out = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path, e)))?;
+    selfplay::play(games, Duration::from_millis(move_time), &mut out)
+}
+
+/// Plays two skill levels against each other and appends the results to a file.
+fn skill_match(args: &[String]) -> io::Result<()> {
+    let usage = || {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "usage: tune skillmatch <level> <level> <games> <results>",
+        )
+    };
+    let (levels, games, path) = match args {
+        [first, second, games, path] => (
+            [
+                first.parse::<i64>().map_err(|_| usage())?,
+                second.parse::<i64>().map_err(|_| usage())?,
+            ],
+            games.parse::<usize>().map_err(|_| usage())?,
+            path,
+        ),
+        _ => return Err(usage()),
+    };
+
+    let mut out = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path, e)))?;
+    selfplay::match_levels(levels, games, &mut out)
+}
+
+/// Fits the WDL model to self-play results, one `<fen> | <eval> | <result>` sample per line.
+fn fit_wdl(path: Option<&String>) -> io::Result<()> {
+    let path = match path {
+        Some(path) => path,
+        None => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "usage: tune fitwdl <samples>",
+            ))
+        }
+    };
+
+    let mut samples = Vec::new();
+    let file =
+        fs::File::open(path).map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path, e)))?;
+    for (lineno, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        match line.parse::<Sample>() {
+            Ok(sample) => samples.push(sample),
+            Err(e) => eprintln!("skipping line {}: {}", lineno + 1, e),
+        }
+    }
+
+    println!("Fitting WDL model to {} samples...", samples.len());
+    let model = WdlModel::fit(&samples);
+    println!("a: {:?}", model.a);
+    println!("b: {:?}", model.b);
+
+    Ok(())
+}
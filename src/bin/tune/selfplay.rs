@@ -0,0 +1,245 @@
+use chess::{ChessMove, MoveGen, EMPTY};
+use log::info;
+use rand::Rng;
+use std::io::{self, Write};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::thread::JoinHandle;
+use std::time::Duration;
+use vampirc_uci::{UciInfoAttribute, UciMessage, UciTimeControl};
+
+use chessengine::engine::options;
+use chessengine::engine::position::Position;
+use chessengine::engine::timeman::to_uci;
+use chessengine::engine::{self, Engine, EngineMessage};
+
+/* Games are played from the start position with a few random moves first, so they differ. */
+const RANDOM_PLIES: std::ops::RangeInclusive<usize> = 2..=4;
+
+/* Long games are adjudicated as draws. */
+const MAX_PLIES: u16 = 400;
+
+/* Small enough that the table is cleared quickly between games. */
+const HASH_MB: &str = "16";
+
+/* Skill levels stop at their node limits, long before this. */
+const SKILL_MOVE_TIME: Duration = Duration::from_secs(60);
+
+/* How a side searches. */
+#[derive(Clone, Copy)]
+enum Player {
+    MoveTime(Duration),
+    /* with the hash cleared before every move, so the sides don't share what they found */
+    Level(i64),
+}
+
+/* An engine whose output comes back on the receiver instead of stdout. */
+fn start_engine() -> (
+    JoinHandle<()>,
+    SyncSender<EngineMessage>,
+    Receiver<UciMessage>,
+) {
+    let (tx, rx) = mpsc::channel();
+    engine::redirect_output(move |message| {
+        let _ = tx.send(message);
+    });
+    let (handle, engine) = Engine::default().start();
+
+    let _ = engine.send(
+        UciMessage::SetOption {
+            name: options::HASH.name.to_string(),
+            value: Some(HASH_MB.to_string()),
+        }
+        .into(),
+    );
+
+    (handle, engine, rx)
+}
+
+fn stop_engine(handle: JoinHandle<()>, engine: SyncSender<EngineMessage>) {
+    let _ = engine.send(UciMessage::Quit.into());
+    info!("Joining engine controller thread...");
+    let _ = handle.join();
+}
+
+/// Plays the engine against itself and writes a `<fen> | <eval> | <result>` sample for
+/// `tune fitwdl` for every quiet position it searched. Positions in check or where
+/// the best move is a capture are left out, since their evaluation is about to change.
+pub fn play(games: usize, move_time: Duration, out: &mut impl Write) -> io::Result<()> {
+    let (handle, engine, rx) = start_engine();
+
+    let mut score = [0; 3];
+    for game in 0..games {
+        let players = [Player::MoveTime(move_time); 2];
+        let (result, samples) = play_game(&engine, &rx, players);
+        for (fen, eval) in samples.iter() {
+            writeln!(out, "{} | {} | {}", fen, eval, result)?;
+        }
+        out.flush()?;
+
+        match result {
+            "1-0" => score[0] += 1,
+            "0-1" => score[2] += 1,
+            _ => score[1] += 1,
+        }
+        eprintln!(
+            "game {}: {}, {} samples (+{} ={} -{})",
+            game + 1,
+            result,
+            samples.len(),
+            score[0],
+            score[1],
+            score[2]
+        );
+    }
+
+    stop_engine(handle, engine);
+    Ok(())
+}
+
+/// Plays skill level `levels[0]` against `levels[1]`, swapping colours every game, and
+/// writes a `<white level> <black level> <result>` line for every game. Reports the
+/// score of the first level and the Elo difference it gives as it goes.
+pub fn match_levels(levels: [i64; 2], games: usize, out: &mut impl Write) -> io::Result<()> {
+    let (handle, engine, rx) = start_engine();
+
+    let mut points = 0.0;
+    for game in 0..games {
+        let (white, black) = if game % 2 == 0 {
+            (levels[0], levels[1])
+        } else {
+            (levels[1], levels[0])
+        };
+        let (result, _) = play_game(&engine, &rx, [Player::Level(white), Player::Level(black)]);
+        writeln!(out, "{} {} {}", white, black, result)?;
+        out.flush()?;
+
+        let white_points = match result {
+            "1-0" => 1.0,
+            "0-1" => 0.0,
+            _ => 0.5,
+        };
+        points += if white == levels[0] {
+            white_points
+        } else {
+            1.0 - white_points
+        };
+        eprintln!(
+            "game {}: {}, level {} scores {}/{} ({:+.0} Elo)",
+            game + 1,
+            result,
+            levels[0],
+            points,
+            game + 1,
+            elo_difference(points / (game + 1) as f64)
+        );
+    }
+
+    stop_engine(handle, engine);
+    Ok(())
+}
+
+/// The rating difference which makes `score` the expected score.
+pub fn elo_difference(score: f64) -> f64 {
+    let score = score.clamp(0.001, 0.999);
+    -400.0 * (1.0 / score - 1.0).log10()
+}
+
+/* Returns the result and the samples of one game. */
+fn play_game(
+    engine: &SyncSender<EngineMessage>,
+    rx: &Receiver<UciMessage>,
+    players: [Player; 2],
+) -> (&'static str, Vec<(String, i32)>) {
+    let send = |message: UciMessage| {
+        let _ = engine.send(message.into());
+    };
+    send(UciMessage::UciNewGame);
+
+    let mut rng = rand::thread_rng();
+    let random_plies = rng.gen_range(RANDOM_PLIES);
+    let mut position = Position::default();
+    let mut moves = Vec::new();
+    let mut samples = Vec::new();
+
+    loop {
+        if let Some(result) = result(&position) {
+            return (result, samples);
+        }
+
+        let mv = if moves.len() < random_plies {
+            let legal: Vec<ChessMove> = MoveGen::new_legal(&position.board).collect();
+            legal[rng.gen_range(0..legal.len())]
+        } else {
+            let move_time = match players[position.board.side_to_move().to_index()] {
+                Player::MoveTime(move_time) => move_time,
+                Player::Level(level) => {
+                    send(UciMessage::SetOption {
+                        name: options::SKILL_LEVEL.name.to_string(),
+                        value: Some(level.to_string()),
+                    });
+                    send(UciMessage::SetOption {
+                        name: options::CLEAR_HASH.name.to_string(),
+                        value: None,
+                    });
+                    SKILL_MOVE_TIME
+                }
+            };
+
+            send(UciMessage::Position {
+                startpos: true,
+                fen: None,
+                moves: moves.clone(),
+            });
+            send(UciMessage::Go {
+                time_control: Some(UciTimeControl::MoveTime(to_uci(move_time))),
+                search_control: None,
+            });
+
+            let (mv, eval) = match search_result(rx) {
+                Some(result) => result,
+                /* the engine is gone */
+                None => return ("1/2-1/2", samples),
+            };
+            let board = &position.board;
+            let quiet = *board.checkers() == EMPTY && board.piece_on(mv.get_dest()).is_none();
+            if let (Some(eval), true) = (eval, quiet) {
+                samples.push((board.to_string(), eval));
+            }
+            mv
+        };
+
+        position.make_move(mv);
+        moves.push(mv);
+    }
+}
+
+/* Waits for the bestmove, along with the last centipawn score before it. */
+fn search_result(rx: &Receiver<UciMessage>) -> Option<(ChessMove, Option<i32>)> {
+    let mut eval = None;
+
+    for message in rx.iter() {
+        match message {
+            UciMessage::BestMove { best_move, .. } => return Some((best_move, eval)),
+            UciMessage::Info(attrs) => {
+                for attr in attrs.iter() {
+                    if let UciInfoAttribute::Score { cp, mate, .. } = attr {
+                        /* mate scores say nothing about the eval scale */
+                        eval = if mate.is_some() { None } else { *cp };
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    None
+}
+
+/* The result, in PGN notation, if the game is over or has gone on long enough to call a draw. */
+fn result(position: &Position) -> Option<&'static str> {
+    match position.game_end() {
+        Some(end) => Some(end.result()),
+        None if position.ply >= MAX_PLIES => Some("1/2-1/2"),
+        None => None,
+    }
+}
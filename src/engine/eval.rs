@@ -86,7 +86,8 @@ fn count_material(board: &Board) -> Eval {
     for sq in *board.color_combined(Color::Black) {
         if let Some(piece) = board.piece_on(sq).as_ref().map(Piece::to_index) {
             count -= PIECE_VALUES[piece];
-            count -= PIECE_TABLES[piece][sq.get_rank().to_index()][sq.get_file().to_index()];
+            /* the tables are from white's side, so mirror the rank for black */
+            count -= PIECE_TABLES[piece][7 - sq.get_rank().to_index()][sq.get_file().to_index()];
         }
     }
 
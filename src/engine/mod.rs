@@ -1,67 +1,168 @@
-use chess::{Board, ChessMove, Color, Game};
-use std::str::FromStr;
+use chess::{Board, ChessMove};
+use std::collections::VecDeque;
+use std::mem;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc;
 use std::sync::mpsc::{Receiver, SyncSender};
+use std::sync::OnceLock;
 use std::thread;
 use std::thread::JoinHandle;
 use std::time::{Duration, Instant};
-use vampirc_uci::{UciInfoAttribute, UciMessage, UciTimeControl};
+use vampirc_uci::{UciMessage, UciSearchControl, UciTimeControl};
 
 use log::info;
 
+pub mod book;
 pub mod eval;
+pub mod options;
+pub mod position;
 pub mod search;
+pub mod skill;
+pub mod syzygy;
 pub mod threads;
+pub mod timeman;
 pub mod ttable;
+pub mod wdl;
+
+use crate::engine::book::Book;
+use crate::engine::eval::Eval;
+use crate::engine::options::UciOption;
+use crate::engine::position::Position;
+use crate::engine::skill::Skill;
+use crate::engine::syzygy::TB;
+use crate::engine::threads::{SearchLimits, THREADS};
+use crate::engine::ttable::TT;
+
+pub const NAME: &str = "Transparov";
+
+/// Messages handled by the engine controller thread.
+pub enum EngineMessage {
+    /// A message from the GUI or from the search threads.
+    Uci(UciMessage),
+    /// `go ponder`. vampirc-uci drops the ponder flag when a time control is given,
+    /// so the front end passes it along separately.
+    GoPonder {
+        time_control: Option<UciTimeControl>,
+        search_control: Option<UciSearchControl>,
+    },
+    /// The final result of a search, from the main search thread.
+    SearchDone(SearchResult),
+}
 
-use crate::engine::threads::THREADS;
+impl From<UciMessage> for EngineMessage {
+    fn from(message: UciMessage) -> Self {
+        EngineMessage::Uci(message)
+    }
+}
+
+/// The move to play and the reply we expect. `best_move` is None if there are no legal moves.
+#[derive(Clone, Copy, Debug)]
+pub struct SearchResult {
+    pub best_move: Option<ChessMove>,
+    pub ponder: Option<ChessMove>,
+}
 
 struct SearchHandle {
     start_time: Instant,
-    search_length: Option<Duration>,
+    hard_limit: Option<Duration>,
+}
+
+/* A `go` command, along with the position it applies to, if one was set. */
+struct SearchRequest {
+    position: Option<Position>,
+    limits: SearchLimits,
+    /* whether a stop arrived while it was queued, so it's stopped as soon as it starts */
+    stopped: bool,
+}
+
+/// Where the controller is in the protocol. Every `go` is answered by exactly one `bestmove`,
+/// and we're only back to Idle once it has been printed.
+enum State {
+    Idle,
+    Searching(SearchHandle),
+    /// The result of a search which finishes while pondering is held back until `ponderhit` or `stop`.
+    Pondering(SearchHandle, Option<SearchResult>),
+    /// Waiting for the result of a search which has been told to stop.
+    Stopping,
+}
+
+impl State {
+    fn name(&self) -> &'static str {
+        match self {
+            State::Idle => "idle",
+            State::Searching(_) => "searching",
+            State::Pondering(_, None) => "pondering",
+            State::Pondering(_, Some(_)) => "pondering (search finished)",
+            State::Stopping => "stopping",
+        }
+    }
+}
+
+/* Whether `debug on` is in effect. */
+static DEBUG: AtomicBool = AtomicBool::new(false);
+
+pub(crate) fn debug_enabled() -> bool {
+    DEBUG.load(Ordering::Relaxed)
+}
+
+type Output = Box<dyn Fn(UciMessage) + Send + Sync>;
+
+/* Where replies go instead of stdout, if the GUI speaks another protocol. */
+static OUTPUT: OnceLock<Output> = OnceLock::new();
+
+/// Hands all replies to `output` instead of printing them, for a front end which translates them
+/// to another protocol. Has to be called before the engine is started.
+pub fn redirect_output(output: impl Fn(UciMessage) + Send + Sync + 'static) {
+    if OUTPUT.set(Box::new(output)).is_err() {
+        info!("Output is already redirected, ignoring...");
+    }
 }
 
 pub struct Engine {
-    board: Option<Board>,
-    best_move: Option<ChessMove>,
-    channel_tx: SyncSender<UciMessage>,
-    channel_rx: Receiver<UciMessage>,
-    searcher: Option<SearchHandle>,
+    position: Option<Position>,
+    state: State,
+    /* go commands which arrived while another search was running */
+    pending: VecDeque<SearchRequest>,
+    hash_mb: u64,
+    multipv: usize,
+    limit_strength: bool,
+    elo: i64,
+    skill_level: i64,
+    show_wdl: bool,
+    contempt: i64,
+    analyse_mode: bool,
+    book: Option<Book>,
+    own_book: bool,
+    best_book_move: bool,
+    book_depth: i64,
+    move_overhead: Duration,
+    channel_tx: SyncSender<EngineMessage>,
+    channel_rx: Receiver<EngineMessage>,
 }
 
 impl SearchHandle {
-    fn new(search_length: Option<Duration>) -> Self {
+    fn new(hard_limit: Option<Duration>) -> Self {
         let start_time = Instant::now();
 
         SearchHandle {
-            search_length,
+            hard_limit,
             start_time,
         }
     }
 
-    fn search(&mut self, board: &Board, moves: Option<Vec<ChessMove>>, depth: Option<u8>) {
+    /// The hard time limit only applies from the ponderhit on.
+    fn ponderhit(&mut self) {
         info!(
-            "Searching for {:?} at depth {:?}.",
-            self.search_length, depth
+            "Ponderhit. Converting to a regular search for {:?}.",
+            self.hard_limit
         );
-        THREADS.start_thinking(board);
+        self.start_time = Instant::now();
+        THREADS.ponderhit();
     }
 
-    fn search_done(&self) -> bool {
-        if THREADS.stopped() {
-            info!("Search completed on its own.");
-            return true;
-        }
-
-        self.search_length.map_or(false, |dur| {
-            if self.elapsed() >= dur {
-                info!("Search is overdue. Stopping search...");
-                THREADS.stop();
-                true
-            } else {
-                false
-            }
-        })
+    fn overdue(&self) -> bool {
+        self.hard_limit.is_some_and(|limit| self.elapsed() >= limit)
     }
 
     fn elapsed(&self) -> Duration {
@@ -73,17 +174,30 @@ impl Default for Engine {
     fn default() -> Self {
         let (tx, rx) = mpsc::sync_channel(128);
         Engine {
-            board: None,
-            best_move: None,
+            position: None,
+            state: State::Idle,
+            pending: VecDeque::new(),
+            hash_mb: options::HASH.default as u64,
+            multipv: options::MULTI_PV.default as usize,
+            limit_strength: options::LIMIT_STRENGTH.default,
+            elo: options::ELO.default,
+            skill_level: options::SKILL_LEVEL.default,
+            show_wdl: options::SHOW_WDL.default,
+            contempt: options::CONTEMPT.default,
+            analyse_mode: options::ANALYSE_MODE.default,
+            book: None,
+            own_book: options::OWN_BOOK.default,
+            best_book_move: options::BEST_BOOK_MOVE.default,
+            book_depth: options::BOOK_DEPTH.default,
+            move_overhead: Duration::from_millis(options::MOVE_OVERHEAD.default as u64),
             channel_tx: tx,
             channel_rx: rx,
-            searcher: None,
         }
     }
 }
 
 impl Engine {
-    pub fn start(self) -> (JoinHandle<()>, SyncSender<UciMessage>) {
+    pub fn start(self) -> (JoinHandle<()>, SyncSender<EngineMessage>) {
         let tx1 = self.channel_tx.clone();
         let tx2 = self.channel_tx.clone();
 
@@ -96,15 +210,33 @@ impl Engine {
         let timeout = Duration::from_millis(2);
         loop {
             if let Ok(message) = self.channel_rx.recv_timeout(timeout) {
-                if !self.handle_message(message) {
+                let running = match message {
+                    EngineMessage::Uci(message) => self.handle_message(message),
+                    EngineMessage::GoPonder {
+                        time_control,
+                        search_control,
+                    } => {
+                        info!("rx: go ponder");
+                        self.go(time_control, search_control, true);
+                        true
+                    }
+                    EngineMessage::SearchDone(result) => {
+                        self.search_done(result);
+                        true
+                    }
+                };
+
+                if !running {
                     info!("Returning from event loop");
                     return;
                 }
             }
 
-            if let Some(searcher) = self.searcher.as_ref() {
-                if self.best_move.is_some() && searcher.search_done() {
-                    self.searcher = None;
+            if let State::Searching(searcher) = &self.state {
+                if searcher.overdue() {
+                    debug_log("search is overdue, stopping".to_string());
+                    THREADS.stop();
+                    self.set_state(State::Stopping);
                 }
             }
 
@@ -117,46 +249,58 @@ impl Engine {
         match message {
             UciMessage::Uci => {
                 id();
-                //option
+                options::all().into_iter().for_each(reply);
                 uciok();
             }
-            UciMessage::Debug(_) => { /*ignore for now */ }
+            UciMessage::Debug(on) => {
+                DEBUG.store(on, Ordering::Relaxed);
+            }
             UciMessage::IsReady => {
+                self.allocate_hash();
                 readyok();
             }
             UciMessage::Register { later, name, code } => {}
-            UciMessage::Position {
-                startpos,
-                fen,
-                moves,
-            } => {
-                let mut game = if let Some(fen) = fen {
-                    Game::from_str(fen.as_str()).unwrap()
-                } else {
-                    Game::new()
-                };
-
-                for mv in moves {
-                    game.make_move(mv);
+            UciMessage::Position { fen, moves, .. } => {
+                match position::setup(fen.as_ref().map(|fen| fen.as_str()), &moves) {
+                    Ok(position) => self.position = Some(position),
+                    Err(e) => info_string(format!("{}, keeping the previous position", e)),
                 }
-
-                self.board = Some(game.current_position());
             }
-            UciMessage::SetOption { .. } => {}
+            UciMessage::Unknown(ref text, _) if text.trim_start().starts_with("position") => {
+                info_string("malformed position, keeping the previous one".to_string());
+            }
+            UciMessage::SetOption { name, value } => {
+                self.set_option(&name, value.as_deref());
+            }
             UciMessage::UciNewGame => {
-                //create a new game
-                self.board = None;
+                self.stop_search();
+                self.position = None;
+                info!("Clearing search state for a new game...");
+                THREADS.clear();
             }
             UciMessage::Stop => {
-                THREADS.stop();
-                if let Some(best_move) = self.best_move.take() {
-                    bestmove(best_move, None);
-                }
+                self.pending
+                    .iter_mut()
+                    .for_each(|request| request.stopped = true);
+                self.stop();
             }
-
-            UciMessage::PonderHit => {}
+            UciMessage::PonderHit => match mem::replace(&mut self.state, State::Idle) {
+                State::Pondering(_, Some(result)) => {
+                    info!("Search finished while pondering, printing held best move...");
+                    self.finish(result);
+                }
+                State::Pondering(mut searcher, None) => {
+                    searcher.ponderhit();
+                    self.set_state(State::Searching(searcher));
+                }
+                state => {
+                    info!("Not pondering, ignoring ponderhit...");
+                    self.state = state;
+                }
+            },
             UciMessage::Quit => {
                 info!("Told to quit. Shutting down Threadpool...");
+                self.pending.clear();
                 THREADS.quit();
                 info!("Threadpool shut down.");
                 return false;
@@ -165,90 +309,350 @@ impl Engine {
                 time_control,
                 search_control,
             } => {
-                // start calculating
-                let mut search_time: Option<Duration> = None;
-                let mut depth: Option<u8> = None;
-                let mut moves: Option<Vec<ChessMove>> = None;
-
-                if let Some(sctrl) = search_control {
-                    depth = sctrl.depth;
-                    if !sctrl.search_moves.is_empty() {
-                        moves = Some(sctrl.search_moves)
-                    }
-                }
-                if let Some(tctrl) = time_control {
-                    search_time = self
-                        .board
-                        .map(|board| calculate_time(tctrl, board.side_to_move()))
-                        .flatten()
-                }
+                self.go(time_control, search_control, false);
+            }
+            //Lines from a search which has already been reported on are stale.
+            UciMessage::Info(_) if !matches!(self.state, State::Idle) => {
+                reply(message);
+            }
 
-                let mut searcher = SearchHandle::new(search_time);
+            _ => {}
+        }
 
-                if let Some(board) = self.board.as_ref() {
-                    searcher.search(board, moves, depth);
-                }
+        true
+    }
+
+    fn go(
+        &mut self,
+        time_control: Option<UciTimeControl>,
+        search_control: Option<UciSearchControl>,
+        ponder: bool,
+    ) {
+        let request = self.search_request(time_control, search_control, ponder);
+
+        if let State::Idle = self.state {
+            self.start_search(request);
+        } else {
+            info!("Search already running, stopping it first...");
+            self.pending.push_back(request);
+            self.stop();
+        }
+    }
+
+    fn search_request(
+        &self,
+        time_control: Option<UciTimeControl>,
+        search_control: Option<UciSearchControl>,
+        ponder: bool,
+    ) -> SearchRequest {
+        let position = self.position.clone();
+        let mut limits = SearchLimits {
+            multipv: self.multipv,
+            skill: self.skill(),
+            show_wdl: self.show_wdl,
+            ponder,
+            infinite: matches!(time_control, Some(UciTimeControl::Infinite)),
+            contempt: self.contempt as Eval,
+            analysis: self.analyse_mode,
+            ..SearchLimits::default()
+        };
+
+        if let Some(sctrl) = search_control {
+            limits.depth = sctrl.depth;
+            limits.nodes = sctrl.nodes;
+            limits.mate = sctrl.mate.filter(|&moves| moves > 0);
+            limits.search_moves = sctrl.search_moves;
+        }
 
-                self.searcher = Some(searcher);
+        if limits.skill.enabled() {
+            let skill = limits.skill;
+            /* an infinite search must not end on its own, so only the move choice is handicapped */
+            if !limits.infinite {
+                limits.depth = Some(limits.depth.unwrap_or(u8::MAX).min(skill.depth_limit()));
+                limits.nodes = Some(limits.nodes.unwrap_or(u64::MAX).min(skill.node_limit()));
             }
-            UciMessage::BestMove { best_move, .. } => {
-                if self.best_move.is_some() {
-                    info!("printing best move...");
-                    println!("{}", message);
-                    self.best_move = None;
-                } else {
-                    info!("search result received after move already reported, ignoring...");
-                }
+            limits.multipv = limits.multipv.max(skill.multipv());
+        }
+        if let (Some(tctrl), Some(position)) = (time_control, &position) {
+            let to_move = position.board.side_to_move();
+            limits.time = timeman::allocate(tctrl, to_move, self.move_overhead);
+            if let Some(time) = limits.time {
+                debug_log(format!(
+                    "time allocation soft {} ms hard {} ms",
+                    time.soft.as_millis(),
+                    time.hard.as_millis()
+                ));
+            }
+        }
+
+        SearchRequest {
+            position,
+            limits,
+            stopped: false,
+        }
+    }
+
+    fn start_search(&mut self, request: SearchRequest) {
+        let SearchRequest {
+            position,
+            mut limits,
+            ..
+        } = request;
+        let position = match position {
+            Some(position) => position,
+            None => {
+                info_string("No position to search, send a position command first".to_string());
+                self.finish(SearchResult {
+                    best_move: None,
+                    ponder: None,
+                });
+                return;
+            }
+        };
+
+        if let Some(best_move) = self.book_move(&position, &limits) {
+            debug_log(format!("playing book move {}", best_move));
+            let board = position.board.make_move_new(best_move);
+            let ponder = self.book.as_ref().and_then(|book| book.probe(&board, true));
+            self.finish(SearchResult {
+                best_move: Some(best_move),
+                ponder,
+            });
+            return;
+        }
+
+        let searcher = SearchHandle::new(limits.time.map(|time| time.hard));
+
+        self.allocate_hash();
+        info!("Searching with limits {:?}.", limits);
+        limits.search_moves = legal_search_moves(&position.board, limits.search_moves);
+        THREADS.start_thinking(&position, &limits);
+
+        self.set_state(if limits.ponder {
+            State::Pondering(searcher, None)
+        } else {
+            State::Searching(searcher)
+        });
+    }
+
+    /// A move from the opening book, if it's in use and has one for this position.
+    /// Pondering, infinite and analysis searches, and those restricted to some moves, are left
+    /// to the search threads.
+    fn book_move(&self, position: &Position, limits: &SearchLimits) -> Option<ChessMove> {
+        let searching =
+            limits.ponder || limits.infinite || limits.analysis || !limits.search_moves.is_empty();
+        if !self.own_book || searching || position.ply as i64 >= 2 * self.book_depth {
+            return None;
+        }
+
+        self.book
+            .as_ref()?
+            .probe(&position.board, self.best_book_move)
+    }
+
+    fn set_state(&mut self, state: State) {
+        debug_log(format!("state {}", state.name()));
+        self.state = state;
+    }
+
+    /// Tells the search to stop. Its result is printed once it arrives.
+    fn stop(&mut self) {
+        match mem::replace(&mut self.state, State::Stopping) {
+            State::Searching(_) | State::Pondering(_, None) => {
+                THREADS.stop();
+                self.set_state(State::Stopping);
+            }
+            State::Pondering(_, Some(result)) => {
+                info!("Search finished while pondering, printing held best move...");
+                self.finish(result);
+            }
+            state => {
+                info!("No search to stop.");
+                self.state = state;
+            }
+        }
+    }
+
+    fn search_done(&mut self, result: SearchResult) {
+        match mem::replace(&mut self.state, State::Idle) {
+            State::Pondering(searcher, None) => {
+                info!("Search finished while pondering, holding best move until ponderhit...");
+                self.set_state(State::Pondering(searcher, Some(result)));
+            }
+            State::Searching(_) | State::Stopping => {
+                self.finish(result);
+            }
+            state => {
+                info!("Search result received with no search running, ignoring...");
+                self.state = state;
+            }
+        }
+    }
+
+    /* Reports the result of the current search, and starts the next one if any are queued up. */
+    fn finish(&mut self, result: SearchResult) {
+        bestmove(result);
+        self.set_state(State::Idle);
+
+        if let Some(request) = self.pending.pop_front() {
+            let stopped = request.stopped;
+            self.start_search(request);
+            if stopped || !self.pending.is_empty() {
+                self.stop();
+            }
+        }
+    }
+
+    fn set_option(&mut self, name: &str, value: Option<&str>) {
+        if options::HASH.matches(name) {
+            if let Some(mb_size) = options::HASH.parse(value) {
+                info!(
+                    "Hash set to {} MB, allocated on the next isready or go",
+                    mb_size
+                );
+                self.hash_mb = mb_size as u64;
+            }
+        } else if options::CLEAR_HASH.matches(name) {
+            self.stop_search();
+            info!("Clearing transposition table...");
+            THREADS.clear_tt();
+        } else if options::PONDER.matches(name) {
+            info!("Pondering set to {:?}", options::PONDER.parse(value));
+        } else if options::MOVE_OVERHEAD.matches(name) {
+            if let Some(overhead) = options::MOVE_OVERHEAD.parse(value) {
+                info!("Move overhead set to {} ms", overhead);
+                self.move_overhead = Duration::from_millis(overhead as u64);
+            }
+        } else if options::MULTI_PV.matches(name) {
+            if let Some(multipv) = options::MULTI_PV.parse(value) {
+                info!("Searching {} principal variations.", multipv);
+                self.multipv = multipv as usize;
             }
-            UciMessage::Info(attrs) => {
-                //If the search has already stopped, we can ignore this.
-                if !THREADS.stopped() {
-                    self.best_move = attrs
-                        .iter()
-                        .filter_map(|attr| match attr {
-                            UciInfoAttribute::Pv(pv) => pv.get(0).cloned(),
-                            _ => None,
-                        })
-                        .next();
+        } else if options::LIMIT_STRENGTH.matches(name) {
+            if let Some(limit_strength) = options::LIMIT_STRENGTH.parse(value) {
+                info!("Limiting strength set to {}", limit_strength);
+                self.limit_strength = limit_strength;
+            }
+        } else if options::ELO.matches(name) {
+            if let Some(elo) = options::ELO.parse(value) {
+                info!("Elo set to {}", elo);
+                self.elo = elo;
+            }
+        } else if options::SKILL_LEVEL.matches(name) {
+            if let Some(level) = options::SKILL_LEVEL.parse(value) {
+                info!("Skill level set to {}", level);
+                self.skill_level = level;
+            }
+        } else if options::SHOW_WDL.matches(name) {
+            if let Some(show_wdl) = options::SHOW_WDL.parse(value) {
+                info!("Showing WDL statistics set to {}", show_wdl);
+                self.show_wdl = show_wdl;
+            }
+        } else if options::CONTEMPT.matches(name) {
+            if let Some(contempt) = options::CONTEMPT.parse(value) {
+                info!("Contempt set to {}", contempt);
+                self.contempt = contempt;
+            }
+        } else if options::ANALYSE_MODE.matches(name) {
+            if let Some(analyse_mode) = options::ANALYSE_MODE.parse(value) {
+                info!("Analysis mode set to {}", analyse_mode);
+                self.analyse_mode = analyse_mode;
+            }
+        } else if options::SYZYGY_PATH.matches(name) {
+            let path = options::SYZYGY_PATH.parse(value);
+            self.stop_search();
+            let found = TB.init(&path);
+            if !path.is_empty() {
+                info_string(format!(
+                    "found {} tablebases with up to {} pieces",
+                    found,
+                    TB.max_pieces()
+                ));
+            }
+        } else if options::OWN_BOOK.matches(name) {
+            if let Some(own_book) = options::OWN_BOOK.parse(value) {
+                info!("Using own book set to {}", own_book);
+                self.own_book = own_book;
+            }
+        } else if options::BOOK_FILE.matches(name) {
+            let path = options::BOOK_FILE.parse(value);
+            self.book = None;
+            if !path.is_empty() {
+                match Book::open(Path::new(&path)) {
+                    Ok(book) => {
+                        info_string(format!("loaded book {} with {} entries", path, book.len()));
+                        self.book = Some(book);
+                    }
+                    Err(e) => info_string(e),
                 }
             }
+        } else if options::BEST_BOOK_MOVE.matches(name) {
+            if let Some(best_book_move) = options::BEST_BOOK_MOVE.parse(value) {
+                info!("Best book move set to {}", best_book_move);
+                self.best_book_move = best_book_move;
+            }
+        } else if options::BOOK_DEPTH.matches(name) {
+            if let Some(depth) = options::BOOK_DEPTH.parse(value) {
+                info!("Book depth set to {} moves", depth);
+                self.book_depth = depth;
+            }
+        } else if options::THREADS.matches(name) {
+            if let Some(nworkers) = options::THREADS.parse(value) {
+                self.stop_search();
+                info!("Resizing thread pool to {} threads...", nworkers);
+                THREADS.resize(nworkers as usize);
+            }
+        } else {
+            info!("Ignoring unknown option {}", name);
+        }
+    }
 
-            _ => {}
+    /// UCI_LimitStrength takes precedence over Skill Level.
+    /// Analysis is always done at full strength.
+    fn skill(&self) -> Skill {
+        if self.analyse_mode {
+            Skill::default()
+        } else if self.limit_strength {
+            Skill::from_elo(self.elo)
+        } else {
+            Skill::from_level(self.skill_level)
         }
+    }
 
-        true
+    /// Allocates the transposition table at the size of the Hash option, unless it already is.
+    fn allocate_hash(&mut self) {
+        if TT.size_mb() != self.hash_mb {
+            self.stop_search();
+            info!("Resizing transposition table to {} MB...", self.hash_mb);
+            TT.resize(self.hash_mb);
+        }
+    }
+
+    /// Makes sure no search is in flight, stopping the current one if needed.
+    fn stop_search(&mut self) {
+        self.stop();
+        THREADS.wait_for_search_finished();
     }
 }
 
-fn calculate_time(time_control: UciTimeControl, to_move: Color) -> Option<Duration> {
-    match time_control {
-        UciTimeControl::MoveTime(duration) => duration.to_std().ok(),
-        UciTimeControl::TimeLeft {
-            white_time,
-            black_time,
-            moves_to_go,
-            ..
-        } => {
-            match to_move {
-                Color::White => white_time,
-                Color::Black => black_time,
-            }
-            .map(|d| {
-                //Convert from vampirc Duration to std duration.
-                d.to_std().ok()
-            })
-            .flatten()
-            .map(|d| {
-                //Divide by moves until next time control or some sensible default
-                d.div_f32(moves_to_go.unwrap_or(40) as f32)
-            })
-        }
-        _ => None,
+/// Filters out the requested moves which are illegal in this position, reporting them to the GUI.
+fn legal_search_moves(board: &Board, moves: Vec<ChessMove>) -> Vec<ChessMove> {
+    let (legal, illegal): (Vec<_>, Vec<_>) = moves.into_iter().partition(|&mv| board.legal(mv));
+
+    for mv in illegal.iter() {
+        info_string(format!("ignoring illegal searchmove {}", mv));
     }
+
+    if legal.is_empty() && !illegal.is_empty() {
+        info_string("no legal searchmoves given, searching all moves".to_string());
+    }
+
+    legal
 }
+
 fn id() {
     reply(UciMessage::Id {
-        name: Some("Transparov".to_string()),
+        name: Some(NAME.to_string()),
         author: None,
     });
     reply(UciMessage::Id {
@@ -258,10 +662,28 @@ fn id() {
 }
 
 fn reply(message: UciMessage) {
+    if let Some(output) = OUTPUT.get() {
+        output(message);
+        return;
+    }
+
     info!("tx: {:?}", message);
     println!("{}", message);
 }
 
+fn info_string(s: String) {
+    reply(UciMessage::info_string(s));
+}
+
+/// Logs `s`, mirroring it to the GUI while debug mode is on.
+fn debug_log(s: String) {
+    if debug_enabled() {
+        info_string(s);
+    } else {
+        info!("{}", s);
+    }
+}
+
 fn uciok() {
     reply(UciMessage::UciOk);
 }
@@ -270,6 +692,86 @@ fn readyok() {
     reply(UciMessage::ReadyOk);
 }
 
-fn bestmove(best_move: ChessMove, ponder: Option<ChessMove>) {
-    reply(UciMessage::BestMove { best_move, ponder });
+fn bestmove(result: SearchResult) {
+    match result.best_move {
+        Some(best_move) => reply(UciMessage::BestMove {
+            best_move,
+            ponder: result.ponder,
+        }),
+        /* vampirc-uci can't represent the null move */
+        None => match OUTPUT.get() {
+            Some(output) => output(UciMessage::Unknown("bestmove 0000".to_string(), None)),
+            None => {
+                info!("tx: bestmove 0000");
+                println!("bestmove 0000");
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_infinite_skill_limits() {
+        let engine = Engine {
+            limit_strength: true,
+            elo: 1200,
+            ..Engine::default()
+        };
+
+        let timed = engine.search_request(None, None, false);
+        assert!(timed.limits.depth.is_some() && timed.limits.nodes.is_some());
+
+        let infinite = engine.search_request(Some(UciTimeControl::Infinite), None, false);
+        assert!(infinite.limits.skill.enabled());
+        assert_eq!(infinite.limits.depth, None);
+        assert_eq!(infinite.limits.nodes, None);
+        assert_eq!(infinite.limits.multipv, timed.limits.multipv);
+    }
+
+    #[test]
+    fn test_go_go_stop() {
+        let (tx, rx) = mpsc::channel();
+        redirect_output(move |message| {
+            let _ = tx.send(message);
+        });
+        let (handle, engine) = Engine::default().start();
+        let send = |message: UciMessage| engine.send(message.into()).unwrap();
+        let go_infinite = || UciMessage::Go {
+            time_control: Some(UciTimeControl::Infinite),
+            search_control: None,
+        };
+
+        send(UciMessage::SetOption {
+            name: options::HASH.name.to_string(),
+            value: Some("1".to_string()),
+        });
+        send(UciMessage::Position {
+            startpos: true,
+            fen: None,
+            moves: Vec::new(),
+        });
+        send(go_infinite());
+        send(go_infinite());
+        send(UciMessage::Stop);
+
+        let next_reply = || loop {
+            match rx.recv_timeout(Duration::from_secs(10)) {
+                Ok(UciMessage::Info(_)) => {}
+                reply => break reply.ok(),
+            }
+        };
+
+        /* the stop ends the queued search too, so both get their bestmove */
+        assert!(matches!(next_reply(), Some(UciMessage::BestMove { .. })));
+        assert!(matches!(next_reply(), Some(UciMessage::BestMove { .. })));
+
+        send(UciMessage::IsReady);
+        assert_eq!(next_reply(), Some(UciMessage::ReadyOk));
+
+        send(UciMessage::Quit);
+        handle.join().unwrap();
+    }
 }
@@ -0,0 +1,250 @@
+use lazy_static::lazy_static;
+use vampirc_uci::{UciMessage, UciOptionConfig};
+
+use crate::engine::skill;
+use crate::engine::ttable::DEFAULT_TT_SIZE_MB;
+
+/// Lets `setoption` find options by name, whatever their kind.
+pub trait UciOption {
+    fn name(&self) -> &'static str;
+
+    /// Option names are case insensitive.
+    fn matches(&self, name: &str) -> bool {
+        self.name().eq_ignore_ascii_case(name)
+    }
+}
+
+pub struct SpinOption {
+    pub name: &'static str,
+    pub default: i64,
+    pub min: i64,
+    pub max: i64,
+}
+
+impl SpinOption {
+    /// Parses the value of a `setoption` command, clamping it to the allowed range.
+    pub fn parse(&self, value: Option<&str>) -> Option<i64> {
+        value
+            .and_then(|v| v.trim().parse::<i64>().ok())
+            .map(|v| v.clamp(self.min, self.max))
+    }
+
+    fn config(&self) -> UciOptionConfig {
+        UciOptionConfig::Spin {
+            name: self.name.to_string(),
+            default: Some(self.default),
+            min: Some(self.min),
+            max: Some(self.max),
+        }
+    }
+}
+
+impl UciOption for SpinOption {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+}
+
+pub struct CheckOption {
+    pub name: &'static str,
+    pub default: bool,
+}
+
+impl CheckOption {
+    pub fn parse(&self, value: Option<&str>) -> Option<bool> {
+        value.and_then(|v| v.trim().to_ascii_lowercase().parse::<bool>().ok())
+    }
+
+    fn config(&self) -> UciOptionConfig {
+        UciOptionConfig::Check {
+            name: self.name.to_string(),
+            default: Some(self.default),
+        }
+    }
+}
+
+impl UciOption for CheckOption {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+}
+
+pub struct ButtonOption {
+    pub name: &'static str,
+}
+
+impl ButtonOption {
+    fn config(&self) -> UciOptionConfig {
+        UciOptionConfig::Button {
+            name: self.name.to_string(),
+        }
+    }
+}
+
+impl UciOption for ButtonOption {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+}
+
+pub struct StringOption {
+    pub name: &'static str,
+    pub default: &'static str,
+}
+
+impl StringOption {
+    /// GUIs send `<empty>` to clear a string option.
+    pub fn parse(&self, value: Option<&str>) -> String {
+        match value.map(str::trim) {
+            None | Some("<empty>") => String::new(),
+            Some(value) => value.to_string(),
+        }
+    }
+
+    fn config(&self) -> UciOptionConfig {
+        UciOptionConfig::String {
+            name: self.name.to_string(),
+            default: Some(self.default.to_string()),
+        }
+    }
+}
+
+impl UciOption for StringOption {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+}
+
+pub const HASH: SpinOption = SpinOption {
+    name: "Hash",
+    default: DEFAULT_TT_SIZE_MB as i64,
+    min: 1,
+    max: 65536,
+};
+
+pub const CLEAR_HASH: ButtonOption = ButtonOption { name: "Clear Hash" };
+
+pub const MULTI_PV: SpinOption = SpinOption {
+    name: "MultiPV",
+    default: 1,
+    min: 1,
+    max: 256,
+};
+
+pub const MOVE_OVERHEAD: SpinOption = SpinOption {
+    name: "Move Overhead",
+    default: 10,
+    min: 0,
+    max: 5000,
+};
+
+pub const PONDER: CheckOption = CheckOption {
+    name: "Ponder",
+    default: false,
+};
+
+pub const LIMIT_STRENGTH: CheckOption = CheckOption {
+    name: "UCI_LimitStrength",
+    default: false,
+};
+
+/* Approximate. The levels it maps to were only rated against each other in short self-play
+ * matches, good to about 100 Elo a step, and level 0 is put at the minimum by assumption
+ * rather than measured against rated players.
+ */
+pub const ELO: SpinOption = SpinOption {
+    name: "UCI_Elo",
+    default: skill::MAX_ELO,
+    min: skill::MIN_ELO,
+    max: skill::MAX_ELO,
+};
+
+pub const SKILL_LEVEL: SpinOption = SpinOption {
+    name: "Skill Level",
+    default: skill::MAX_LEVEL,
+    min: 0,
+    max: skill::MAX_LEVEL,
+};
+
+pub const SHOW_WDL: CheckOption = CheckOption {
+    name: "UCI_ShowWDL",
+    default: false,
+};
+
+pub const CONTEMPT: SpinOption = SpinOption {
+    name: "Contempt",
+    default: 0,
+    min: -100,
+    max: 100,
+};
+
+/* Directories with Syzygy tablebases, separated like PATH. */
+pub const SYZYGY_PATH: StringOption = StringOption {
+    name: "SyzygyPath",
+    default: "<empty>",
+};
+
+pub const ANALYSE_MODE: CheckOption = CheckOption {
+    name: "UCI_AnalyseMode",
+    default: false,
+};
+
+pub const OWN_BOOK: CheckOption = CheckOption {
+    name: "OwnBook",
+    default: false,
+};
+
+/* A Polyglot opening book. */
+pub const BOOK_FILE: StringOption = StringOption {
+    name: "BookFile",
+    default: "<empty>",
+};
+
+/* Play the heaviest book move instead of picking one at random by weight. */
+pub const BEST_BOOK_MOVE: CheckOption = CheckOption {
+    name: "BestBookMove",
+    default: false,
+};
+
+/* Stop using the book after this many moves of the game. */
+pub const BOOK_DEPTH: SpinOption = SpinOption {
+    name: "BookDepth",
+    default: 255,
+    min: 1,
+    max: 255,
+};
+
+lazy_static! {
+    pub static ref THREADS: SpinOption = SpinOption {
+        name: "Threads",
+        default: num_cpus::get() as i64,
+        min: 1,
+        max: 256,
+    };
+}
+
+/// The `option` messages sent in reply to `uci`.
+pub fn all() -> Vec<UciMessage> {
+    vec![
+        HASH.config(),
+        CLEAR_HASH.config(),
+        THREADS.config(),
+        PONDER.config(),
+        MULTI_PV.config(),
+        MOVE_OVERHEAD.config(),
+        SKILL_LEVEL.config(),
+        LIMIT_STRENGTH.config(),
+        ELO.config(),
+        CONTEMPT.config(),
+        SHOW_WDL.config(),
+        ANALYSE_MODE.config(),
+        SYZYGY_PATH.config(),
+        OWN_BOOK.config(),
+        BOOK_FILE.config(),
+        BEST_BOOK_MOVE.config(),
+        BOOK_DEPTH.config(),
+    ]
+    .into_iter()
+    .map(UciMessage::Option)
+    .collect()
+}
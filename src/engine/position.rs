@@ -0,0 +1,354 @@
+use chess::{Board, BoardStatus, ChessMove, Color, Piece, Square};
+use std::str::FromStr;
+
+/* Piece placement, indexed by rank and then file, both from 0. */
+type Placement = [[Option<(Piece, Color)>; 8]; 8];
+
+/// A position along with the part of the game leading up to it which matters for draws.
+#[derive(Clone, Debug, Default)]
+pub struct Position {
+    pub board: Board,
+    /// Hashes of the earlier positions since the last capture or pawn move, oldest first.
+    pub history: Vec<u64>,
+    /// Plies since the last capture or pawn move.
+    pub halfmove_clock: u16,
+    /// Plies since the start of the game, going by the move number in the FEN.
+    pub ply: u16,
+}
+
+impl Position {
+    /// Plays `mv`, which has to be legal, keeping the history up to date.
+    pub fn make_move(&mut self, mv: ChessMove) {
+        if is_irreversible(&self.board, mv) {
+            self.history.clear();
+            self.halfmove_clock = 0;
+        } else {
+            self.history.push(self.board.get_hash());
+            self.halfmove_clock = self.halfmove_clock.saturating_add(1);
+        }
+
+        self.ply = self.ply.saturating_add(1);
+        self.board = self.board.make_move_new(mv);
+    }
+
+    /// How the game has ended by the rules, if it has.
+    pub fn game_end(&self) -> Option<GameEnd> {
+        let board = &self.board;
+        let repetitions = self
+            .history
+            .iter()
+            .filter(|&&hash| hash == board.get_hash())
+            .count();
+
+        match board.status() {
+            BoardStatus::Checkmate => Some(GameEnd::Checkmate(!board.side_to_move())),
+            BoardStatus::Stalemate => Some(GameEnd::Stalemate),
+            _ if self.halfmove_clock >= 100 => Some(GameEnd::FiftyMoves),
+            _ if repetitions >= 2 => Some(GameEnd::Repetition),
+            _ if insufficient_material(board) => Some(GameEnd::InsufficientMaterial),
+            _ => None,
+        }
+    }
+}
+
+/// Why a game is over.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GameEnd {
+    /// Mate, by the given side.
+    Checkmate(Color),
+    Stalemate,
+    FiftyMoves,
+    Repetition,
+    InsufficientMaterial,
+}
+
+impl GameEnd {
+    /// The result in PGN notation.
+    pub fn result(self) -> &'static str {
+        match self {
+            GameEnd::Checkmate(Color::White) => "1-0",
+            GameEnd::Checkmate(Color::Black) => "0-1",
+            _ => "1/2-1/2",
+        }
+    }
+}
+
+/// Whether neither side can mate anymore: bare kings, or a single minor piece left.
+pub fn insufficient_material(board: &Board) -> bool {
+    let minors = board.pieces(Piece::Knight) | board.pieces(Piece::Bishop);
+    board.combined().popcnt() - minors.popcnt() == 2 && minors.popcnt() <= 1
+}
+
+/// Whether `mv` resets the fifty-move counter, making all earlier positions unreachable.
+pub(crate) fn is_irreversible(board: &Board, mv: ChessMove) -> bool {
+    board.piece_on(mv.get_source()) == Some(Piece::Pawn) || board.piece_on(mv.get_dest()).is_some()
+}
+
+/// Sets up the position given by a `position` command.
+/// Returns a description of the problem if the FEN is invalid or one of the moves is illegal.
+pub fn setup(fen: Option<&str>, moves: &[ChessMove]) -> Result<Position, String> {
+    let mut position = match fen {
+        Some(fen) => parse_fen(fen)?,
+        None => Position::default(),
+    };
+
+    for (idx, &mv) in moves.iter().enumerate() {
+        if !position.board.legal(mv) {
+            return Err(format!("illegal move {} (move {})", mv, idx + 1));
+        }
+        position.make_move(mv);
+    }
+
+    Ok(position)
+}
+
+/// Parses and validates a FEN string.
+/// The chess crate panics or accepts nonsense on some malformed input, so check it ourselves first.
+pub fn parse_fen(fen: &str) -> Result<Position, String> {
+    let fields: Vec<&str> = fen.split_whitespace().collect();
+    if fields.len() < 4 || fields.len() > 6 {
+        return Err(format!(
+            "invalid fen {}: expected 4 to 6 fields, got {}",
+            fen,
+            fields.len()
+        ));
+    }
+
+    let placement = parse_placement(fields[0])?;
+
+    if fields[1] != "w" && fields[1] != "b" {
+        return Err(format!("invalid side to move {}", fields[1]));
+    }
+
+    check_kings(&placement)?;
+    check_pawns(&placement)?;
+    check_castling(&placement, fields[2])?;
+
+    if fields[3] != "-" {
+        let rank = if fields[1] == "w" { '6' } else { '3' };
+        let valid = Square::from_str(fields[3]).is_ok() && fields[3].ends_with(rank);
+        if !valid {
+            return Err(format!("invalid en passant square {}", fields[3]));
+        }
+    }
+
+    let mut counters = Vec::new();
+    for counter in fields.iter().skip(4) {
+        match counter.parse::<u16>() {
+            Ok(n) => counters.push(n),
+            Err(_) => return Err(format!("invalid move counter {}", counter)),
+        }
+    }
+
+    let board = Board::from_str(fen).map_err(|e| format!("invalid fen {}: {}", fen, e))?;
+    let fullmove = counters.get(1).copied().unwrap_or(1).max(1);
+    let black = (board.side_to_move() == Color::Black) as u16;
+
+    Ok(Position {
+        board,
+        history: Vec::new(),
+        halfmove_clock: counters.first().copied().unwrap_or(0),
+        ply: (fullmove - 1).saturating_mul(2).saturating_add(black),
+    })
+}
+
+fn parse_placement(pieces: &str) -> Result<Placement, String> {
+    let mut placement: Placement = [[None; 8]; 8];
+    let ranks: Vec<&str> = pieces.split('/').collect();
+
+    if ranks.len() != 8 {
+        return Err(format!("expected 8 ranks, got {}", ranks.len()));
+    }
+
+    for (idx, rank) in ranks.iter().enumerate() {
+        let rank_idx = 7 - idx;
+        let mut file = 0;
+
+        for c in rank.chars() {
+            if let Some(skip) = c.to_digit(10).filter(|n| (1..=8).contains(n)) {
+                file += skip as usize;
+                continue;
+            }
+
+            let piece = match c.to_ascii_lowercase() {
+                'p' => Piece::Pawn,
+                'n' => Piece::Knight,
+                'b' => Piece::Bishop,
+                'r' => Piece::Rook,
+                'q' => Piece::Queen,
+                'k' => Piece::King,
+                _ => return Err(format!("invalid piece {}", c)),
+            };
+            let color = if c.is_ascii_uppercase() {
+                Color::White
+            } else {
+                Color::Black
+            };
+
+            if file < 8 {
+                placement[rank_idx][file] = Some((piece, color));
+            }
+            file += 1;
+        }
+
+        if file != 8 {
+            return Err(format!("rank {} has {} files", rank_idx + 1, file));
+        }
+    }
+
+    Ok(placement)
+}
+
+fn count(placement: &Placement, piece: Piece, color: Color) -> usize {
+    placement
+        .iter()
+        .flatten()
+        .filter(|&&sq| sq == Some((piece, color)))
+        .count()
+}
+
+fn check_kings(placement: &Placement) -> Result<(), String> {
+    for &color in [Color::White, Color::Black].iter() {
+        let kings = count(placement, Piece::King, color);
+        if kings != 1 {
+            return Err(format!("{:?} has {} kings", color, kings));
+        }
+    }
+
+    Ok(())
+}
+
+fn check_pawns(placement: &Placement) -> Result<(), String> {
+    for &rank in [0, 7].iter() {
+        if placement[rank]
+            .iter()
+            .any(|sq| matches!(sq, Some((Piece::Pawn, _))))
+        {
+            return Err(format!("pawn on rank {}", rank + 1));
+        }
+    }
+
+    Ok(())
+}
+
+/* Castling rights require the king and the rook on their original squares. */
+fn check_castling(placement: &Placement, castling: &str) -> Result<(), String> {
+    if castling == "-" {
+        return Ok(());
+    }
+
+    let mut seen = Vec::new();
+    for c in castling.chars() {
+        let (color, rook_file) = match c {
+            'K' => (Color::White, 7),
+            'Q' => (Color::White, 0),
+            'k' => (Color::Black, 7),
+            'q' => (Color::Black, 0),
+            _ => return Err(format!("invalid castling rights {}", castling)),
+        };
+
+        if seen.contains(&c) {
+            return Err(format!("invalid castling rights {}", castling));
+        }
+        seen.push(c);
+
+        let rank = if color == Color::White { 0 } else { 7 };
+        let king = placement[rank][4] == Some((Piece::King, color));
+        let rook = placement[rank][rook_file] == Some((Piece::Rook, color));
+
+        if !king || !rook {
+            return Err(format!(
+                "castling right {} without king and rook on their original squares",
+                c
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_fen() {
+        let start = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+        assert_eq!(parse_fen(start).map(|p| p.board), Ok(Board::default()));
+
+        /* missing black king */
+        assert!(parse_fen("8/8/8/8/8/8/8/4K3 w - - 0 1").is_err());
+        /* pawn on the back rank */
+        assert!(parse_fen("4k2P/8/8/8/8/8/8/4K3 w - - 0 1").is_err());
+        /* castling without a rook */
+        assert!(parse_fen("4k3/8/8/8/8/8/8/4K3 w K - 0 1").is_err());
+        /* nine files */
+        assert!(parse_fen("4k4/8/8/8/8/8/8/4K3 w - - 0 1").is_err());
+        assert!(parse_fen("4k3/8/8/8/8/8/8/4K3 x - - 0 1").is_err());
+        assert!(parse_fen("garbage").is_err());
+    }
+
+    #[test]
+    fn test_setup() {
+        let e2e4 = ChessMove::new(Square::E2, Square::E4, None);
+        let e7e5 = ChessMove::new(Square::E7, Square::E5, None);
+
+        assert_eq!(setup(None, &[e2e4, e7e5]).map(|p| p.ply), Ok(2));
+        assert!(setup(None, &[e2e4, e2e4]).is_err());
+
+        let fen = "4k3/8/8/8/8/8/8/4K3 b - - 0 10";
+        assert_eq!(setup(Some(fen), &[]).map(|p| p.ply), Ok(19));
+    }
+
+    #[test]
+    fn test_history() {
+        let g1f3 = ChessMove::new(Square::G1, Square::F3, None);
+        let g8f6 = ChessMove::new(Square::G8, Square::F6, None);
+        let f3g1 = ChessMove::new(Square::F3, Square::G1, None);
+        let e2e4 = ChessMove::new(Square::E2, Square::E4, None);
+
+        let position = setup(None, &[g1f3, g8f6, f3g1]).unwrap();
+        assert_eq!(position.history.len(), 3);
+        assert_eq!(position.history[0], Board::default().get_hash());
+        assert_eq!(position.halfmove_clock, 3);
+
+        let position = setup(None, &[g1f3, g8f6, e2e4]).unwrap();
+        assert!(position.history.is_empty());
+        assert_eq!(position.halfmove_clock, 0);
+    }
+
+    #[test]
+    fn test_game_end() {
+        let moves: Vec<ChessMove> = ["f2f3", "e7e5", "g2g4", "d8h4"]
+            .iter()
+            .map(|mv| ChessMove::from_str(mv).unwrap())
+            .collect();
+        let end = setup(None, &moves).unwrap().game_end();
+        assert_eq!(end, Some(GameEnd::Checkmate(Color::Black)));
+        assert_eq!(end.map(GameEnd::result), Some("0-1"));
+
+        let game_end = |fen| parse_fen(fen).unwrap().game_end();
+        assert_eq!(
+            game_end("4k3/8/8/8/8/8/8/4KN2 w - - 0 1"),
+            Some(GameEnd::InsufficientMaterial)
+        );
+        assert_eq!(game_end("4k3/8/8/8/8/8/8/4KR2 w - - 0 1"), None);
+        assert_eq!(
+            game_end("4k3/8/8/8/8/8/8/4KR2 w - - 100 80"),
+            Some(GameEnd::FiftyMoves)
+        );
+        assert_eq!(
+            game_end("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1"),
+            Some(GameEnd::Stalemate)
+        );
+
+        let knight_dance: Vec<ChessMove> = ["g1f3", "g8f6", "f3g1", "f6g8"]
+            .iter()
+            .map(|mv| ChessMove::from_str(mv).unwrap())
+            .collect();
+        let once = setup(None, &knight_dance).unwrap();
+        assert_eq!(once.game_end(), None);
+        let twice = setup(None, &[knight_dance.clone(), knight_dance].concat()).unwrap();
+        assert_eq!(twice.game_end().map(GameEnd::result), Some("1/2-1/2"));
+    }
+}
@@ -2,7 +2,12 @@ use chess::{Board, BoardStatus, ChessMove, Color, MoveGen};
 use log::info;
 use std::cmp::Reverse;
 use std::panic::Location;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 
+use crate::engine::position::{self, Position};
+use crate::engine::syzygy::{WdlScore, TB};
+use crate::engine::threads::{SearchLimits, THREADS};
 use crate::engine::ttable::{EntryType, EvalMove, TT};
 
 use super::eval;
@@ -11,6 +16,279 @@ use eval::Eval;
 pub(crate) const SCORE_MATE: Eval = 32_000;
 pub(crate) const SCORE_INF: Eval = 32_001;
 
+/* Scores beyond this bound are mate scores. */
+const MAX_PLY: Eval = 256;
+const SCORE_MATE_BOUND: Eval = SCORE_MATE - MAX_PLY;
+
+/* Tablebase wins rank below all mates, but above any evaluation. */
+const SCORE_TB_WIN: Eval = SCORE_MATE_BOUND - MAX_PLY;
+/* Scores beyond this bound are tablebase wins or mates. */
+const SCORE_TB_WIN_BOUND: Eval = SCORE_TB_WIN - MAX_PLY;
+
+/// The score for a tablebase win reached `root_distance` plies from the root.
+pub(crate) fn tb_win_in(root_distance: u8) -> Eval {
+    SCORE_TB_WIN - root_distance as Eval
+}
+
+/// The score for delivering mate `root_distance` plies from the root.
+pub(crate) fn mate_in(root_distance: u8) -> Eval {
+    SCORE_MATE - root_distance as Eval
+}
+
+/// The score for being mated `root_distance` plies from the root.
+pub(crate) fn mated_in(root_distance: u8) -> Eval {
+    -SCORE_MATE + root_distance as Eval
+}
+
+/// Converts a root score to the number of moves until mate.
+/// Positive if the side to move at the root is mating, negative if it is getting mated.
+pub(crate) fn moves_to_mate(score: Eval) -> Option<i8> {
+    if score >= SCORE_MATE_BOUND {
+        Some(((SCORE_MATE - score + 1) / 2).min(i8::MAX as Eval) as i8)
+    } else if score <= -SCORE_MATE_BOUND {
+        Some(-((SCORE_MATE + score) / 2).min(i8::MAX as Eval) as i8)
+    } else {
+        None
+    }
+}
+
+/* Mate and tablebase win scores are relative to the root during the search,
+ * but relative to the position itself in the transposition table.
+ */
+fn value_to_tt(score: Eval, root_distance: u8) -> Eval {
+    if score >= SCORE_TB_WIN_BOUND {
+        score + root_distance as Eval
+    } else if score <= -SCORE_TB_WIN_BOUND {
+        score - root_distance as Eval
+    } else {
+        score
+    }
+}
+
+fn value_from_tt(score: Eval, root_distance: u8) -> Eval {
+    if score >= SCORE_TB_WIN_BOUND {
+        score - root_distance as Eval
+    } else if score <= -SCORE_TB_WIN_BOUND {
+        score + root_distance as Eval
+    } else {
+        score
+    }
+}
+
+/* How many nodes the main thread searches between checks of the node limit. */
+const NODE_CHECK_INTERVAL: u64 = 256;
+
+/// The node counters of all workers in the pool, handed to each of them so they can
+/// total them up without going through the pool.
+pub type NodeCounters = Arc<[Arc<AtomicU64>]>;
+
+/// Search state private to a single worker thread.
+pub struct SearchContext {
+    is_main: bool,
+    nodes: Arc<AtomicU64>,
+    pool_nodes: NodeCounters,
+    node_limit: Option<u64>,
+    /* an infinite search which reached its node limit waits for stop instead of stopping */
+    infinite: bool,
+    node_limit_reached: bool,
+    seldepth: u8,
+    /* Hashes of the positions leading up to the current node, from the game and the search,
+     * along with their halfmove clocks.
+     */
+    history: Vec<(u64, u16)>,
+    /* where the root is in the history, everything before it was played in the game */
+    root: usize,
+    halfmove_clock: u16,
+    /* the side the engine is playing, which the contempt is relative to */
+    us: Color,
+    contempt: Eval,
+    /* Triangular PV table. Row n holds the principal variation
+     * of the node n plies from the root.
+     */
+    pv_table: Vec<Vec<ChessMove>>,
+}
+
+impl Default for SearchContext {
+    fn default() -> Self {
+        SearchContext::new(false, Arc::default())
+    }
+}
+
+impl SearchContext {
+    pub fn new(is_main: bool, nodes: Arc<AtomicU64>) -> Self {
+        SearchContext {
+            is_main,
+            nodes,
+            pool_nodes: NodeCounters::default(),
+            node_limit: None,
+            infinite: false,
+            node_limit_reached: false,
+            seldepth: 0,
+            history: Vec::new(),
+            root: 0,
+            halfmove_clock: 0,
+            us: Color::White,
+            contempt: 0,
+            pv_table: vec![Vec::new(); MAX_PLY as usize + 1],
+        }
+    }
+
+    /// The principal variation of the last node searched `root_distance` plies from the root.
+    pub fn pv(&self, root_distance: u8) -> &[ChessMove] {
+        &self.pv_table[root_distance as usize]
+    }
+
+    pub(crate) fn clear_pv(&mut self, root_distance: u8) {
+        self.pv_table[root_distance as usize].clear();
+    }
+
+    /* mv is the new best move at this node, followed by the child's PV. */
+    fn update_pv(&mut self, root_distance: u8, mv: ChessMove) {
+        let (parents, children) = self.pv_table.split_at_mut(root_distance as usize + 1);
+        let pv = &mut parents[root_distance as usize];
+
+        pv.clear();
+        pv.push(mv);
+        pv.extend_from_slice(&children[0]);
+    }
+
+    /// Forgets everything from previous searches.
+    pub fn clear(&mut self) {
+        self.nodes.store(0, Ordering::Relaxed);
+        self.node_limit = None;
+        self.infinite = false;
+        self.node_limit_reached = false;
+        self.seldepth = 0;
+        self.history.clear();
+        self.root = 0;
+        self.halfmove_clock = 0;
+        self.contempt = 0;
+        self.pv_table.iter_mut().for_each(Vec::clear);
+    }
+
+    pub fn reset(&mut self, position: &Position, limits: &SearchLimits) {
+        self.nodes.store(0, Ordering::Relaxed);
+        self.node_limit = limits.nodes;
+        self.infinite = limits.infinite;
+        self.node_limit_reached = false;
+        self.seldepth = 0;
+
+        /* the game history before the root is only used for comparing hashes */
+        self.history.clear();
+        self.history
+            .extend(position.history.iter().map(|&hash| (hash, 0)));
+        self.root = self.history.len();
+        self.halfmove_clock = position.halfmove_clock;
+
+        self.us = position.board.side_to_move();
+        self.contempt = if limits.analysis { 0 } else { limits.contempt };
+    }
+
+    /// Records that `mv` is about to be searched from `board`.
+    pub(crate) fn push_move(&mut self, board: &Board, mv: ChessMove) {
+        self.history.push((board.get_hash(), self.halfmove_clock));
+        self.halfmove_clock = if position::is_irreversible(board, mv) {
+            0
+        } else {
+            self.halfmove_clock.saturating_add(1)
+        };
+    }
+
+    pub(crate) fn pop_move(&mut self) {
+        if let Some((_, halfmove_clock)) = self.history.pop() {
+            self.halfmove_clock = halfmove_clock;
+        }
+    }
+
+    /// Whether the current node, `board`, is a draw by the fifty-move rule or by repetition.
+    /// A single repetition within the search counts, since whatever was best the first time
+    /// will be again. Positions from the game before the root need a real threefold repetition.
+    pub(crate) fn is_draw(&self, board: &Board) -> bool {
+        if self.halfmove_clock >= 100 && board.status() != BoardStatus::Checkmate {
+            return true;
+        }
+
+        /* only positions with the same side to move, since the last irreversible move */
+        let hash = board.get_hash();
+        let mut repetitions = self
+            .history
+            .iter()
+            .enumerate()
+            .rev()
+            .take(self.halfmove_clock as usize)
+            .skip(1)
+            .step_by(2)
+            .filter(|&(_, &(key, _))| key == hash)
+            .map(|(idx, _)| idx);
+
+        match repetitions.next() {
+            Some(idx) if idx > self.root => true,
+            Some(_) => repetitions.next().is_some(),
+            None => false,
+        }
+    }
+
+    /// The score of a draw at `board`, from the side to move's point of view.
+    /// With contempt, the engine considers draws worse than equality and the opponent better.
+    pub(crate) fn draw_score(&self, board: &Board) -> Eval {
+        if board.side_to_move() == self.us {
+            -self.contempt
+        } else {
+            self.contempt
+        }
+    }
+
+    /// Sets the counters nodes_searched() totals up, which includes our own.
+    pub fn share_nodes(&mut self, pool_nodes: NodeCounters) {
+        self.pool_nodes = pool_nodes;
+    }
+
+    /// The total number of nodes searched by all workers in the current search.
+    pub fn nodes_searched(&self) -> u64 {
+        self.pool_nodes
+            .iter()
+            .map(|nodes| nodes.load(Ordering::Relaxed))
+            .sum()
+    }
+
+    /// Whether this worker should give up on its search, because the search was stopped
+    /// or because it used up the node limit of an infinite search.
+    pub fn stopped(&self) -> bool {
+        self.node_limit_reached || THREADS.stopped()
+    }
+
+    /// The deepest ply reached, including the quiescence search.
+    pub fn seldepth(&self) -> u8 {
+        self.seldepth
+    }
+
+    pub(crate) fn visit_node(&mut self, root_distance: u8) {
+        self.seldepth = self.seldepth.max(root_distance);
+
+        let nodes = self.nodes.fetch_add(1, Ordering::Relaxed) + 1;
+
+        if !self.is_main {
+            return;
+        }
+
+        if let Some(limit) = self.node_limit {
+            /* our own count is a cheap lower bound of the total */
+            let reached = nodes >= limit
+                || (nodes.is_multiple_of(NODE_CHECK_INTERVAL) && self.nodes_searched() >= limit);
+
+            if reached && !self.stopped() {
+                if self.infinite {
+                    info!("Node limit reached, waiting for stop...");
+                    self.node_limit_reached = true;
+                } else {
+                    info!("Node limit reached. Stopping search...");
+                    THREADS.stop();
+                }
+            }
+        }
+    }
+}
+
 #[track_caller]
 pub(crate) fn make_move_new(board: &Board, mv: ChessMove) -> Option<Board> {
     if !board.legal(mv) {
@@ -26,6 +304,39 @@ pub(crate) fn make_move_new(board: &Board, mv: ChessMove) -> Option<Board> {
     }
 }
 
+/// Follows the best moves stored in the transposition table from `board`,
+/// stopping at the first missing or illegal move.
+pub(crate) fn tt_line(board: &Board, max_len: u8) -> Vec<ChessMove> {
+    let mut line = Vec::new();
+    let mut pos = *board;
+
+    while line.len() < max_len as usize {
+        let mv = match TT.probe(&pos).0 {
+            Some(te) => ChessMove::from(te.mv),
+            None => break,
+        };
+
+        if !pos.legal(mv) {
+            break;
+        }
+
+        line.push(mv);
+        pos = pos.make_move_new(mv);
+    }
+
+    line
+}
+
+/// Looks up the expected reply to `mv` in the transposition table.
+pub(crate) fn ponder_move(board: &Board, mv: ChessMove) -> Option<ChessMove> {
+    let pos = board.make_move_new(mv);
+
+    TT.probe(&pos)
+        .0
+        .map(|te| ChessMove::from(te.mv))
+        .filter(|&reply| pos.legal(reply))
+}
+
 fn order_moves(board: &Board, best_move: Option<&EvalMove>) -> Vec<EvalMove> {
     let legal = MoveGen::new_legal(board);
 
@@ -53,28 +364,53 @@ fn order_moves(board: &Board, best_move: Option<&EvalMove>) -> Vec<EvalMove> {
 }
 
 pub fn alphabeta(
+    ctx: &mut SearchContext,
     board: Board,
     mut alpha: Eval,
     mut beta: Eval,
     depth: u8,
     root_distance: u8,
 ) -> Eval {
+    ctx.clear_pv(root_distance);
+
+    if ctx.stopped() {
+        return 0;
+    }
+
+    ctx.visit_node(root_distance);
+
     match board.status() {
         BoardStatus::Checkmate => {
-            return -SCORE_MATE;
+            return mated_in(root_distance);
         }
         BoardStatus::Stalemate => {
-            return 0;
+            return ctx.draw_score(&board);
         }
         _ => {}
     }
 
+    if ctx.is_draw(&board) {
+        return ctx.draw_score(&board);
+    }
+
+    /* right after a capture or pawn move the outcome doesn't depend on the fifty-move counter */
+    if ctx.halfmove_clock == 0 && TB.covers(&board) {
+        if let Some(wdl) = TB.probe_wdl(&board) {
+            THREADS.count_tb_hits(1);
+            return match wdl {
+                WdlScore::Win => tb_win_in(root_distance),
+                WdlScore::Loss => -tb_win_in(root_distance),
+                _ => ctx.draw_score(&board),
+            };
+        }
+    }
+
     if depth == 0 {
-        return quiesce(board, alpha, beta);
+        return quiesce(ctx, board, alpha, beta, root_distance);
         //return eval::evaluate_board(&board);
     }
 
-    let mating_score = SCORE_MATE - root_distance as Eval;
+    let mating_score = mate_in(root_distance);
 
     let mut max = Eval::MIN;
 
@@ -83,11 +419,13 @@ pub fn alphabeta(
     let mut tt_move: Option<ChessMove> = None;
 
     if let Some(te) = table_entry {
-        if te.depth >= depth {
+        let tt_eval = value_from_tt(te.eval, root_distance);
+
+        if te.depth >= depth && te.entry_type().cuts_off(tt_eval, alpha, beta) {
             /* we already have a deeper evaluation cached, so just return it. */
-            return te.eval;
+            return tt_eval;
         } else {
-            best_move = Some(EvalMove::new(te.mv.into(), te.eval));
+            best_move = Some(EvalMove::new(te.mv.into(), tt_eval));
             tt_move = Some(te.mv.into());
         }
     }
@@ -112,12 +450,15 @@ pub fn alphabeta(
          * an improvement is possible.
          * If the position is previously unseen, do a regular alpha/beta search.
          */
-        let score = -alphabeta(pos, -beta, -alpha, depth - 1, root_distance + 1);
+        ctx.push_move(&board, mv);
+        let score = -alphabeta(ctx, pos, -beta, -alpha, depth - 1, root_distance + 1);
+        ctx.pop_move();
 
         //info!("{}eval {}: {}(depth {})", indentation, mv, score, depth);
 
         if score >= beta {
-            TT.save(handle, &board, mv, score, depth, EntryType::Cut);
+            let tt_score = value_to_tt(score, root_distance);
+            TT.save(handle, &board, mv, tt_score, depth, EntryType::Cut);
             return score;
             //return quiesce(board, alpha, beta);
         }
@@ -127,6 +468,7 @@ pub fn alphabeta(
             max = score;
             if score > alpha {
                 alpha = score;
+                ctx.update_pv(root_distance, mv);
             }
         }
 
@@ -153,23 +495,26 @@ pub fn alphabeta(
             EntryType::Pv
         };
 
-        TT.save(handle, &board, mv, eval, depth, entry_type);
+        let tt_eval = value_to_tt(eval, root_distance);
+        TT.save(handle, &board, mv, tt_eval, depth, entry_type);
     }
 
-    if max >= SCORE_MATE - depth as Eval {
-        max - 1
-    } else if max < -SCORE_MATE + depth as Eval {
-        max + 1
-    } else {
-        max
-    }
+    max
 }
 
 static DELTA_MARGIN: Eval = 200;
 
-fn quiesce(board: Board, mut alpha: Eval, beta: Eval) -> Eval {
+fn quiesce(
+    ctx: &mut SearchContext,
+    board: Board,
+    mut alpha: Eval,
+    beta: Eval,
+    root_distance: u8,
+) -> Eval {
+    ctx.visit_node(root_distance);
+
     if board.status() == BoardStatus::Checkmate {
-        return -SCORE_MATE;
+        return mated_in(root_distance);
     }
     let cur_eval = eval::evaluate_board(&board);
 
@@ -190,7 +535,13 @@ fn quiesce(board: Board, mut alpha: Eval, beta: Eval) -> Eval {
     captures.set_iterator_mask(*board.color_combined(min_color));
 
     for mv in captures {
-        let score = -quiesce(board.make_move_new(mv), -beta, -alpha);
+        let score = -quiesce(
+            ctx,
+            board.make_move_new(mv),
+            -beta,
+            -alpha,
+            root_distance.saturating_add(1),
+        );
 
         if score >= beta {
             return beta;
@@ -201,3 +552,135 @@ fn quiesce(board: Board, mut alpha: Eval, beta: Eval) -> Eval {
 
     alpha
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_moves_to_mate() {
+        assert_eq!(moves_to_mate(mate_in(1)), Some(1));
+        assert_eq!(moves_to_mate(mate_in(3)), Some(2));
+        assert_eq!(moves_to_mate(mated_in(0)), Some(0));
+        assert_eq!(moves_to_mate(mated_in(2)), Some(-1));
+        assert_eq!(moves_to_mate(mated_in(4)), Some(-2));
+        assert_eq!(moves_to_mate(150), None);
+        assert_eq!(moves_to_mate(-150), None);
+    }
+
+    #[test]
+    fn test_repetition() {
+        use chess::Square;
+
+        let knight_dance = [
+            ChessMove::new(Square::G1, Square::F3, None),
+            ChessMove::new(Square::G8, Square::F6, None),
+            ChessMove::new(Square::F3, Square::G1, None),
+            ChessMove::new(Square::F6, Square::G8, None),
+        ];
+        let mut ctx = SearchContext::default();
+        let search = |ctx: &mut SearchContext, position: &Position, moves: &[ChessMove]| {
+            let mut board = position.board;
+            for &mv in moves.iter() {
+                ctx.push_move(&board, mv);
+                board = board.make_move_new(mv);
+            }
+            board
+        };
+
+        /* back to the start position in the game, which only makes a threefold the second time */
+        let position = position::setup(None, &knight_dance).unwrap();
+        ctx.reset(&position, &SearchLimits::default());
+        assert!(!ctx.is_draw(&position.board));
+
+        let twice = [knight_dance, knight_dance].concat();
+        let position = position::setup(None, &twice).unwrap();
+        ctx.reset(&position, &SearchLimits::default());
+        assert!(ctx.is_draw(&position.board));
+
+        /* the first half was played in the game, the second half is searched, so the start
+         * position has only been seen once before the root
+         */
+        let position = position::setup(None, &knight_dance[..2]).unwrap();
+        ctx.reset(&position, &SearchLimits::default());
+        let board = search(&mut ctx, &position, &knight_dance[2..]);
+        assert!(!ctx.is_draw(&board));
+
+        /* but a position first reached within the search is a draw the second time */
+        let position = position::setup(None, &[]).unwrap();
+        ctx.reset(&position, &SearchLimits::default());
+        let board = search(&mut ctx, &position, &knight_dance);
+        assert!(!ctx.is_draw(&board));
+        ctx.push_move(&board, knight_dance[0]);
+        assert!(ctx.is_draw(&board.make_move_new(knight_dance[0])));
+
+        ctx.pop_move();
+        assert!(!ctx.is_draw(&board));
+    }
+
+    #[test]
+    fn test_contempt() {
+        let limits = SearchLimits {
+            contempt: 20,
+            ..SearchLimits::default()
+        };
+        let mut ctx = SearchContext::default();
+
+        /* playing black, so draws are bad whenever black is to move */
+        let e2e4 = ChessMove::new(chess::Square::E2, chess::Square::E4, None);
+        let position = position::setup(None, &[e2e4]).unwrap();
+        ctx.reset(&position, &limits);
+        assert_eq!(ctx.draw_score(&position.board), -20);
+        assert_eq!(ctx.draw_score(&Board::default()), 20);
+
+        let analysis = SearchLimits {
+            analysis: true,
+            ..limits
+        };
+        ctx.reset(&position, &analysis);
+        assert_eq!(ctx.draw_score(&position.board), 0);
+    }
+
+    #[test]
+    fn test_infinite_node_limit() {
+        let limits = SearchLimits {
+            nodes: Some(1),
+            infinite: true,
+            ..SearchLimits::default()
+        };
+        let mut ctx = SearchContext::new(true, Arc::default());
+        ctx.reset(&position::setup(None, &[]).unwrap(), &limits);
+        assert!(!ctx.stopped());
+
+        /* the main thread gives up on its search, but leaves stopping to the GUI */
+        ctx.visit_node(0);
+        assert!(ctx.stopped());
+        assert!(!THREADS.stopped());
+    }
+
+    #[test]
+    fn test_tt_mate_scores() {
+        /* mate in 3 plies from the root, stored 2 plies from the root */
+        let score = mate_in(3);
+        let stored = value_to_tt(score, 2);
+        assert_eq!(stored, mate_in(1));
+        assert_eq!(value_from_tt(stored, 2), score);
+
+        let score = mated_in(4);
+        assert_eq!(value_from_tt(value_to_tt(score, 1), 1), score);
+        assert_eq!(value_to_tt(42, 7), 42);
+    }
+
+    #[test]
+    fn test_tt_tb_scores() {
+        /* a tablebase win found 5 plies from the root, stored 3 plies from the root */
+        let score = tb_win_in(5);
+        let stored = value_to_tt(score, 3);
+        assert_eq!(stored, tb_win_in(2));
+        assert_eq!(value_from_tt(stored, 3), score);
+
+        /* probed again through a transposition 7 plies from the root */
+        assert_eq!(value_from_tt(stored, 7), tb_win_in(9));
+        assert_eq!(value_from_tt(value_to_tt(-score, 3), 7), -tb_win_in(9));
+    }
+}
@@ -0,0 +1,126 @@
+use chess::ChessMove;
+use rand::Rng;
+use std::cmp::min;
+
+use crate::engine::eval::PIECE_VALUES;
+use crate::engine::search::SCORE_INF;
+use crate::engine::threads::RootMove;
+
+pub const MAX_LEVEL: i64 = 20;
+
+/* Where level 0 sits. Nothing rated was available to measure against,
+ * so this anchor is a guess; only the differences above it are measured.
+ */
+pub const MIN_ELO: i64 = 800;
+pub const MAX_ELO: i64 = MIN_ELO + LEVEL_ELO[LEVEL_ELO.len() - 1];
+
+/// Rating of every even level over level 0, from the matches in tests/skill/ladder.txt:
+/// 40 games of each level against the one two below it, which tests/skill/ladder.sh
+/// plays and sums up. Level 20 searches without limits, so it can't be measured that
+/// way and is extrapolated from the last step instead. Each step is good to about
+/// ±100 Elo, and self-play tends to stretch the differences.
+const LEVEL_ELO: [i64; 11] = [0, 255, 470, 808, 987, 1215, 1455, 1696, 1833, 1960, 2087];
+
+/* The number of root moves the skill handicap chooses from. */
+const CANDIDATES: usize = 4;
+
+/// Handicaps which make the engine play weaker than it can.
+///
+/// The level goes from 0 (beginner) to 20 (full strength). Each level searches
+/// about 1.7 times the nodes of the level below it (2^0.75), and makes the choice
+/// between the best root moves less random.
+#[derive(Clone, Copy, Debug)]
+pub struct Skill {
+    level: f64,
+}
+
+impl Skill {
+    pub fn from_level(level: i64) -> Self {
+        Skill {
+            level: level.clamp(0, MAX_LEVEL) as f64,
+        }
+    }
+
+    /// Maps UCI_Elo onto the skill levels, interpolating between the measured ratings.
+    /// Those only approximate the strength of each level, see LEVEL_ELO.
+    pub fn from_elo(elo: i64) -> Self {
+        let elo = elo.clamp(MIN_ELO, MAX_ELO) - MIN_ELO;
+        let step = LEVEL_ELO[1..]
+            .iter()
+            .position(|&rating| elo < rating)
+            .unwrap_or(LEVEL_ELO.len() - 2);
+        let (low, high) = (LEVEL_ELO[step], LEVEL_ELO[step + 1]);
+        let level = 2.0 * (step as f64 + (elo - low) as f64 / (high - low) as f64);
+
+        Skill { level }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.level < MAX_LEVEL as f64
+    }
+
+    pub fn depth_limit(&self) -> u8 {
+        1 + self.level as u8
+    }
+
+    pub fn node_limit(&self) -> u64 {
+        (100.0 * 2f64.powf(self.level * 0.75)) as u64
+    }
+
+    /// The number of root moves which need exact scores for pick_move to choose from.
+    pub fn multipv(&self) -> usize {
+        CANDIDATES
+    }
+
+    /// Chooses among the best root moves, favouring the better ones more the higher the level is.
+    /// `moves` must be sorted, best move first.
+    pub fn pick_move(&self, moves: &[RootMove]) -> Option<ChessMove> {
+        /* moves which failed low have no exact score to compare */
+        let exact = moves.iter().take_while(|rm| rm.eval > -SCORE_INF).count();
+        let candidates = &moves[..min(CANDIDATES, exact)];
+        let top = candidates.first()?.eval as i32;
+        let last = candidates.last()?.eval as i32;
+
+        let pawn = PIECE_VALUES[0] as i32;
+        let delta = min(top - last, pawn);
+        let weakness = (120.0 - 2.0 * self.level) as i32;
+        let mut rng = rand::thread_rng();
+
+        /* Worse moves get a bigger push, so that they all end up close to the
+         * best one, with some randomness added on top.
+         */
+        candidates
+            .iter()
+            .map(|rm| {
+                let eval = rm.eval as i32;
+                let push = (weakness * (top - eval) + delta * rng.gen_range(0..weakness)) / 128;
+                (eval + push, rm.mv)
+            })
+            .max_by_key(|&(score, _)| score)
+            .map(|(_, mv)| mv)
+    }
+}
+
+impl Default for Skill {
+    fn default() -> Self {
+        Skill::from_level(MAX_LEVEL)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_elo_mapping() {
+        assert!(!Skill::from_elo(MAX_ELO).enabled());
+        assert!(Skill::from_elo(MAX_ELO - 1).enabled());
+        assert_eq!(Skill::from_elo(MIN_ELO).depth_limit(), 1);
+        assert!(Skill::from_elo(1200).node_limit() < Skill::from_elo(1280).node_limit());
+
+        for (step, rating) in LEVEL_ELO.iter().enumerate() {
+            let level = Skill::from_elo(MIN_ELO + rating).level;
+            assert!((level - 2.0 * step as f64).abs() < 1e-9, "{}", level);
+        }
+    }
+}
@@ -0,0 +1,1566 @@
+use chess::{
+    BitBoard, Board, BoardStatus, CastleRights, ChessMove, Color, MoveGen, Piece, Square,
+    ALL_PIECES, ALL_SQUARES, EMPTY,
+};
+use lazy_static::lazy_static;
+use log::info;
+use std::cmp::min;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::ops::{Deref, Neg};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock, RwLock};
+
+use crate::engine::position::{self, Position};
+
+/* Only the fixture generator (examples/gentb.rs) and the tests need the solver. */
+#[cfg(any(test, feature = "tablegen"))]
+pub mod generate;
+
+lazy_static! {
+    pub static ref TB: Tablebases = Tablebases::default();
+    static ref INDICES: Indices = Indices::new();
+}
+
+const WDL_MAGIC: [u8; 4] = [0x71, 0xE8, 0x23, 0x5D];
+const DTZ_MAGIC: [u8; 4] = [0xD7, 0x66, 0x0C, 0xA5];
+
+/* The most pieces the file format allows for. */
+const MAX_PIECES: usize = 7;
+
+/* Flags of the compressed data for one side and file. */
+const STM: u8 = 1;
+const MAPPED: u8 = 2;
+const WIN_PLIES: u8 = 4;
+const LOSS_PLIES: u8 = 8;
+const WIDE: u8 = 16;
+const SINGLE_VALUE: u8 = 128;
+
+/* Which of the four DTZ value maps to use, indexed by WdlScore + 2. */
+const WDL_MAP: [usize; 5] = [1, 3, 0, 2, 0];
+
+/* Piece letters as they appear in the table names. */
+const PIECE_CHARS: [(char, Piece); 6] = [
+    ('K', Piece::King),
+    ('Q', Piece::Queen),
+    ('R', Piece::Rook),
+    ('B', Piece::Bishop),
+    ('N', Piece::Knight),
+    ('P', Piece::Pawn),
+];
+
+/// A tablebase result for the side to move. Cursed wins and blessed losses are
+/// wins and losses which the fifty-move rule turns into draws.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum WdlScore {
+    Loss = -2,
+    BlessedLoss = -1,
+    Draw = 0,
+    CursedWin = 1,
+    Win = 2,
+}
+
+impl WdlScore {
+    fn from_value(value: i32) -> Self {
+        match value {
+            i32::MIN..=-2 => WdlScore::Loss,
+            -1 => WdlScore::BlessedLoss,
+            0 => WdlScore::Draw,
+            1 => WdlScore::CursedWin,
+            _ => WdlScore::Win,
+        }
+    }
+}
+
+impl Neg for WdlScore {
+    type Output = WdlScore;
+
+    fn neg(self) -> WdlScore {
+        WdlScore::from_value(-(self as i32))
+    }
+}
+
+/* How a probe went, besides failing. */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ProbeState {
+    Ok,
+    /* the best move resets the fifty-move counter, so the DTZ table can't be trusted */
+    ZeroingBestMove,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Kind {
+    Wdl,
+    Dtz,
+}
+
+/* Piece counts, four bits per piece type and colour, the side listed first in the low bits. */
+fn material_key(counts: &[[u8; 6]; 2]) -> u64 {
+    counts
+        .iter()
+        .flatten()
+        .enumerate()
+        .map(|(idx, &n)| (n as u64) << (4 * idx))
+        .sum()
+}
+
+fn board_counts(board: &Board) -> [[u8; 6]; 2] {
+    let mut counts = [[0; 6]; 2];
+    for (side, &color) in [Color::White, Color::Black].iter().enumerate() {
+        for &piece in ALL_PIECES.iter() {
+            let pieces = board.pieces(piece) & board.color_combined(color);
+            counts[side][piece.to_index()] = pieces.popcnt() as u8;
+        }
+    }
+
+    counts
+}
+
+/* What's known about a table from its name alone, like KRPvKR. */
+#[derive(Clone, Debug)]
+struct Material {
+    /* with the first side in the name as white */
+    key: u64,
+    /* with the colours swapped */
+    key2: u64,
+    piece_count: usize,
+    has_pawns: bool,
+    /* whether any side has a single piece of some type, besides the king */
+    has_unique_pieces: bool,
+    /* the pawns of the leading colour first */
+    pawn_count: [usize; 2],
+}
+
+impl Material {
+    fn from_name(name: &str) -> Option<Material> {
+        let sides: Vec<&str> = name.split('v').collect();
+        if sides.len() != 2 {
+            return None;
+        }
+
+        let mut counts = [[0u8; 6]; 2];
+        for (side, pieces) in sides.iter().enumerate() {
+            if !pieces.starts_with('K') {
+                return None;
+            }
+            for c in pieces.chars() {
+                let &(_, piece) = PIECE_CHARS.iter().find(|&&(pc, _)| pc == c)?;
+                counts[side][piece.to_index()] += 1;
+            }
+        }
+
+        let piece_count = counts.iter().flatten().map(|&n| n as usize).sum();
+        let kings = Piece::King.to_index();
+        if piece_count > MAX_PIECES || counts[0][kings] != 1 || counts[1][kings] != 1 {
+            return None;
+        }
+
+        let pawns = Piece::Pawn.to_index();
+        let (white_pawns, black_pawns) = (counts[0][pawns] as usize, counts[1][pawns] as usize);
+        /* the side with fewer pawns leads, since that compresses better */
+        let white_leads = black_pawns == 0 || (white_pawns > 0 && black_pawns >= white_pawns);
+        let pawn_count = if white_leads {
+            [white_pawns, black_pawns]
+        } else {
+            [black_pawns, white_pawns]
+        };
+
+        let has_unique_pieces = counts
+            .iter()
+            .any(|side| side[..Piece::King.to_index()].contains(&1));
+
+        Some(Material {
+            key: material_key(&counts),
+            key2: material_key(&[counts[1], counts[0]]),
+            piece_count,
+            has_pawns: white_pawns + black_pawns > 0,
+            has_unique_pieces,
+            pawn_count,
+        })
+    }
+}
+
+/* Lookup tables for turning piece placements into table indices. */
+struct Indices {
+    binomial: [[u64; 64]; MAX_PIECES],
+    map_pawns: [usize; 64],
+    lead_pawn_idx: [[u64; 64]; MAX_PIECES],
+    lead_pawns_size: [[u64; 4]; MAX_PIECES],
+    map_b1h1h7: [usize; 64],
+    map_a1d1d4: [usize; 64],
+    map_kk: [[u64; 64]; 10],
+}
+
+/* Positive above the a1-h8 diagonal, negative below it. */
+fn off_diagonal(sq: usize) -> i32 {
+    (sq >> 3) as i32 - (sq & 7) as i32
+}
+
+impl Indices {
+    fn new() -> Self {
+        let mut indices = Indices {
+            binomial: [[0; 64]; MAX_PIECES],
+            map_pawns: [0; 64],
+            lead_pawn_idx: [[0; 64]; MAX_PIECES],
+            lead_pawns_size: [[0; 4]; MAX_PIECES],
+            map_b1h1h7: [0; 64],
+            map_a1d1d4: [0; 64],
+            map_kk: [[0; 64]; 10],
+        };
+
+        /* the squares below the a1-h8 diagonal */
+        for (code, sq) in (0..64).filter(|&sq| off_diagonal(sq) < 0).enumerate() {
+            indices.map_b1h1h7[sq] = code;
+        }
+
+        /* the a1-d1-d4 triangle, with the diagonal squares last */
+        let mut diagonal = Vec::new();
+        let mut code = 0;
+        for sq in (0..=27).filter(|&sq| sq & 7 <= 3) {
+            if off_diagonal(sq) < 0 {
+                indices.map_a1d1d4[sq] = code;
+                code += 1;
+            } else if off_diagonal(sq) == 0 {
+                diagonal.push(sq);
+            }
+        }
+        for sq in diagonal {
+            indices.map_a1d1d4[sq] = code;
+            code += 1;
+        }
+
+        /* The 462 legal placements of two kings with the first one in the triangle.
+         * If the first king is on the diagonal, the second one can't be above it.
+         */
+        let mut both_on_diagonal = Vec::new();
+        let mut code = 0;
+        for idx in 0..10 {
+            for (s1, &square) in ALL_SQUARES.iter().enumerate().take(28) {
+                if indices.map_a1d1d4[s1] != idx || (idx == 0 && square != Square::B1) {
+                    continue;
+                }
+
+                let touching = chess::get_king_moves(square) | BitBoard::from_square(square);
+                for (s2, &other) in ALL_SQUARES.iter().enumerate() {
+                    if touching & BitBoard::from_square(other) != EMPTY {
+                        continue;
+                    }
+
+                    if off_diagonal(s1) == 0 && off_diagonal(s2) > 0 {
+                        continue;
+                    } else if off_diagonal(s1) == 0 && off_diagonal(s2) == 0 {
+                        both_on_diagonal.push((idx, s2));
+                    } else {
+                        indices.map_kk[idx][s2] = code;
+                        code += 1;
+                    }
+                }
+            }
+        }
+        for (idx, s2) in both_on_diagonal {
+            indices.map_kk[idx][s2] = code;
+            code += 1;
+        }
+
+        indices.binomial[0][0] = 1;
+        for n in 1..64 {
+            for k in 0..min(MAX_PIECES, n + 1) {
+                let with = if k > 0 {
+                    indices.binomial[k - 1][n - 1]
+                } else {
+                    0
+                };
+                let without = if k < n { indices.binomial[k][n - 1] } else { 0 };
+                indices.binomial[k][n] = with + without;
+            }
+        }
+
+        /* The pawn squares a2-h7, from the edges in. The leading pawn is the one
+         * with the highest number, and the others can only be on lower numbers.
+         */
+        let mut available = 47;
+        for lead_pawns in 1..MAX_PIECES - 1 {
+            for file in 0..4 {
+                let mut idx = 0;
+                for rank in 1..7 {
+                    let sq = rank * 8 + file;
+                    if lead_pawns == 1 {
+                        indices.map_pawns[sq] = available;
+                        indices.map_pawns[sq ^ 7] = available - 1;
+                        available = available.saturating_sub(2);
+                    }
+                    indices.lead_pawn_idx[lead_pawns][sq] = idx;
+                    idx += indices.binomial[lead_pawns - 1][indices.map_pawns[sq]];
+                }
+                indices.lead_pawns_size[lead_pawns][file] = idx;
+            }
+        }
+
+        indices
+    }
+}
+
+fn read_le(data: &[u8], offset: usize, len: usize) -> u64 {
+    data.get(offset..offset + len).map_or(0, |bytes| {
+        bytes.iter().rev().fold(0, |acc, &b| acc << 8 | b as u64)
+    })
+}
+
+fn read_be(data: &[u8], offset: usize, len: usize) -> u64 {
+    data.get(offset..offset + len).map_or(0, |bytes| {
+        bytes.iter().fold(0, |acc, &b| acc << 8 | b as u64)
+    })
+}
+
+/* The compressed values for one side to move and, in pawn tables, one file of the leading pawn. */
+#[derive(Clone, Default)]
+struct PairsData {
+    flags: u8,
+    pieces: [u8; MAX_PIECES],
+    /* the number of pieces in each group, terminated by 0 */
+    group_len: [usize; MAX_PIECES + 1],
+    /* the index multiplier of each group, the last one being the table size */
+    group_idx: [u64; MAX_PIECES + 1],
+    block_size: usize,
+    span: u64,
+    num_blocks: usize,
+    block_length_size: usize,
+    min_sym_len: u8,
+    /* the rest are offsets into the file */
+    lowest_sym: usize,
+    base64: Vec<u64>,
+    /* the number of values each symbol stands for, minus one */
+    symlen: Vec<u8>,
+    btree: usize,
+    sparse_index: usize,
+    sparse_index_size: usize,
+    block_lengths: usize,
+    data: usize,
+    map_idx: [usize; 4],
+}
+
+impl PairsData {
+    fn set_groups(&mut self, material: &Material, order: [u8; 2], file: usize) {
+        let indices = &*INDICES;
+        let mut first_len: i32 = if material.has_pawns {
+            0
+        } else if material.has_unique_pieces {
+            3
+        } else {
+            2
+        };
+
+        /* Groups are runs of identical pieces, except that the leading group
+         * without pawns has the first two or three pieces.
+         */
+        let mut n = 0;
+        self.group_len[0] = 1;
+        for i in 1..material.piece_count {
+            first_len -= 1;
+            if first_len > 0 || self.pieces[i] == self.pieces[i - 1] {
+                self.group_len[n] += 1;
+            } else {
+                n += 1;
+                self.group_len[n] = 1;
+            }
+        }
+        n += 1;
+        self.group_len[n] = 0;
+
+        /* The groups are encoded in the order the table specifies, with the
+         * leading group at order[0] and the remaining pawns at order[1].
+         */
+        let pp = material.has_pawns && material.pawn_count[1] > 0;
+        let mut next = if pp { 2 } else { 1 };
+        let mut free_squares = 64 - self.group_len[0] - if pp { self.group_len[1] } else { 0 };
+        let mut idx: u64 = 1;
+
+        let mut k = 0;
+        while next < n || k == order[0] as usize || k == order[1] as usize {
+            if k == order[0] as usize {
+                self.group_idx[0] = idx;
+                idx *= if material.has_pawns {
+                    indices.lead_pawns_size[self.group_len[0]][file]
+                } else if material.has_unique_pieces {
+                    31332
+                } else {
+                    462
+                };
+            } else if k == order[1] as usize {
+                self.group_idx[1] = idx;
+                idx *= indices.binomial[self.group_len[1]][48 - self.group_len[0]];
+            } else {
+                self.group_idx[next] = idx;
+                idx *= indices.binomial[self.group_len[next]][free_squares];
+                free_squares -= self.group_len[next];
+                next += 1;
+            }
+            k += 1;
+        }
+
+        self.group_idx[n] = idx;
+    }
+
+    /* Reads the Huffman code and the block layout. Returns the offset after them. */
+    fn set_sizes(&mut self, data: &[u8], mut pos: usize) -> Option<usize> {
+        self.flags = *data.get(pos)?;
+        pos += 1;
+
+        if self.flags & SINGLE_VALUE != 0 {
+            /* the value every position has */
+            self.min_sym_len = *data.get(pos)?;
+            return Some(pos + 1);
+        }
+
+        let groups = self.group_len.iter().position(|&len| len == 0)?;
+        let tb_size = self.group_idx[groups];
+
+        self.block_size = 1 << *data.get(pos)?;
+        self.span = 1 << *data.get(pos + 1)?;
+        self.sparse_index_size = tb_size.div_ceil(self.span) as usize;
+        let padding = *data.get(pos + 2)? as usize;
+        self.num_blocks = read_le(data, pos + 3, 4) as usize;
+        self.block_length_size = self.num_blocks + padding;
+        let max_sym_len = *data.get(pos + 7)?;
+        self.min_sym_len = *data.get(pos + 8)?;
+        pos += 9;
+
+        if max_sym_len < self.min_sym_len || max_sym_len > 64 {
+            return None;
+        }
+
+        /* Canonical Huffman code, where longer codes have lower values. base64[i] is
+         * the lowest code of length min_sym_len + i, left aligned to 64 bits.
+         */
+        self.lowest_sym = pos;
+        let lengths = (max_sym_len - self.min_sym_len) as usize + 1;
+        let lowest_sym = self.lowest_sym;
+        let lowest = |i: usize| read_le(data, lowest_sym + 2 * i, 2);
+        self.base64 = vec![0; lengths];
+        for i in (0..lengths - 1).rev() {
+            self.base64[i] = (self.base64[i + 1] + lowest(i)).wrapping_sub(lowest(i + 1)) / 2;
+        }
+        for (i, base) in self.base64.iter_mut().enumerate() {
+            let shift = 64 - i as u32 - self.min_sym_len as u32;
+            *base = base.checked_shl(shift).unwrap_or(0);
+        }
+        pos += 2 * lengths;
+
+        /* Recursive pairing: every symbol stands for a pair of other symbols, or a value. */
+        let symbols = read_le(data, pos, 2) as usize;
+        pos += 2;
+        self.btree = pos;
+        self.symlen = vec![0; symbols];
+        let mut visited = vec![false; symbols];
+        for sym in 0..symbols {
+            if !visited[sym] {
+                self.symlen[sym] = self.set_symlen(data, sym, &mut visited);
+            }
+        }
+
+        Some(pos + 3 * symbols + (symbols & 1))
+    }
+
+    fn set_symlen(&mut self, data: &[u8], sym: usize, visited: &mut [bool]) -> u8 {
+        visited[sym] = true;
+        let (left, right) = self.pair(data, sym);
+        if right == 0xFFF || left >= visited.len() || right >= visited.len() {
+            return 0;
+        }
+
+        for &child in [left, right].iter() {
+            if !visited[child] {
+                self.symlen[child] = self.set_symlen(data, child, visited);
+            }
+        }
+
+        self.symlen[left]
+            .wrapping_add(self.symlen[right])
+            .wrapping_add(1)
+    }
+
+    /* The two symbols `sym` stands for. A leaf symbol stores its value on the left. */
+    fn pair(&self, data: &[u8], sym: usize) -> (usize, usize) {
+        let lr = read_le(data, self.btree + 3 * sym, 3) as usize;
+        (lr & 0xFFF, lr >> 12)
+    }
+
+    fn block_length(&self, data: &[u8], block: usize) -> i64 {
+        read_le(data, self.block_lengths + 2 * block, 2) as i64
+    }
+
+    /* The value stored for the position with index `idx`. */
+    fn decompress(&self, data: &[u8], idx: u64) -> u16 {
+        if self.flags & SINGLE_VALUE != 0 {
+            return self.min_sym_len as u16;
+        }
+
+        /* The sparse index points into the blocks every `span` values, so start
+         * from the nearest entry and walk the block lengths from there.
+         */
+        let entry = self.sparse_index + 6 * (idx / self.span) as usize;
+        let mut block = read_le(data, entry, 4) as usize;
+        let mut offset = read_le(data, entry + 4, 2) as i64;
+        offset += (idx % self.span) as i64 - (self.span / 2) as i64;
+
+        while offset < 0 && block > 0 {
+            block -= 1;
+            offset += self.block_length(data, block) + 1;
+        }
+        while offset > self.block_length(data, block) && block < self.block_length_size {
+            offset -= self.block_length(data, block) + 1;
+            block += 1;
+        }
+
+        /* Decode symbols until reaching the one which covers our offset. */
+        let mut ptr = self.data + block * self.block_size;
+        let mut buf64 = read_be(data, ptr, 8);
+        let mut buf64_size = 64;
+        ptr += 8;
+
+        let min_sym_len = self.min_sym_len as usize;
+        let mut sym;
+        loop {
+            let mut len = 0;
+            while len + 1 < self.base64.len() && buf64 < self.base64[len] {
+                len += 1;
+            }
+
+            let shift = (64 - len - min_sym_len) as u32;
+            sym = buf64
+                .wrapping_sub(self.base64[len])
+                .checked_shr(shift)
+                .unwrap_or(0) as usize;
+            sym += read_le(data, self.lowest_sym + 2 * len, 2) as usize;
+
+            let covered = *self.symlen.get(sym).unwrap_or(&0) as i64 + 1;
+            if offset < covered {
+                break;
+            }
+
+            offset -= covered;
+            len += min_sym_len;
+            buf64 = buf64.checked_shl(len as u32).unwrap_or(0);
+            buf64_size -= len;
+
+            if buf64_size <= 32 {
+                buf64_size += 32;
+                buf64 |= read_be(data, ptr, 4) << (64 - buf64_size);
+                ptr += 4;
+            }
+        }
+
+        /* Then expand the symbol down to the single value at our offset. */
+        while *self.symlen.get(sym).unwrap_or(&0) != 0 {
+            let (left, right) = self.pair(data, sym);
+            let covered = self.symlen[left] as i64 + 1;
+
+            if offset < covered {
+                sym = left;
+            } else {
+                offset -= covered;
+                sym = right;
+            }
+        }
+
+        self.pair(data, sym).0 as u16
+    }
+}
+
+/* The contents of a table file. Files are memory mapped where possible, so that
+ * only the parts which are probed get read, and the OS can page them out again.
+ */
+enum TableData {
+    #[cfg(unix)]
+    Mapped(Mmap),
+    #[cfg(any(test, not(unix)))]
+    Owned(Vec<u8>),
+}
+
+impl TableData {
+    #[cfg(unix)]
+    fn open(path: &Path) -> io::Result<TableData> {
+        Mmap::open(path).map(TableData::Mapped)
+    }
+
+    #[cfg(not(unix))]
+    fn open(path: &Path) -> io::Result<TableData> {
+        fs::read(path).map(TableData::Owned)
+    }
+}
+
+impl Deref for TableData {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            #[cfg(unix)]
+            TableData::Mapped(mmap) => mmap,
+            #[cfg(any(test, not(unix)))]
+            TableData::Owned(data) => data,
+        }
+    }
+}
+
+/* A read-only mapping of a whole file. */
+#[cfg(unix)]
+struct Mmap {
+    ptr: *mut libc::c_void,
+    len: usize,
+}
+
+/* The mapping is never written to, so it can be shared like a &[u8]. */
+#[cfg(unix)]
+unsafe impl Send for Mmap {}
+#[cfg(unix)]
+unsafe impl Sync for Mmap {}
+
+#[cfg(unix)]
+impl Mmap {
+    fn open(path: &Path) -> io::Result<Mmap> {
+        use std::os::unix::io::AsRawFd;
+
+        let file = fs::File::open(path)?;
+        let len = file.metadata()?.len() as usize;
+        if len == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "empty file"));
+        }
+
+        /* the mapping stays valid after the file is closed */
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_SHARED,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Mmap { ptr, len })
+    }
+}
+
+#[cfg(unix)]
+impl Deref for Mmap {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.ptr as *const u8, self.len) }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.ptr, self.len);
+        }
+    }
+}
+
+/* A parsed table file. */
+struct Table {
+    data: TableData,
+    /* indexed by side to move and then by the file of the leading pawn */
+    pairs: [Vec<PairsData>; 2],
+}
+
+impl Table {
+    fn parse(data: TableData, kind: Kind, material: &Material) -> Option<Table> {
+        let magic = match kind {
+            Kind::Wdl => WDL_MAGIC,
+            Kind::Dtz => DTZ_MAGIC,
+        };
+        if data.get(..4) != Some(&magic[..]) {
+            return None;
+        }
+
+        let has_pawns = data.get(4)? & 2 != 0;
+        if has_pawns != material.has_pawns {
+            return None;
+        }
+
+        let sides = if kind == Kind::Wdl && material.key != material.key2 {
+            2
+        } else {
+            1
+        };
+        let files = if material.has_pawns { 4 } else { 1 };
+        let pp = material.has_pawns && material.pawn_count[1] > 0;
+        let mut pairs: [Vec<PairsData>; 2] = Default::default();
+        let mut pos = 5;
+
+        for file in 0..files {
+            let first = *data.get(pos)?;
+            let second = if pp { *data.get(pos + 1)? } else { 0xFF };
+            let order = [[first & 0xF, second & 0xF], [first >> 4, second >> 4]];
+            pos += 1 + pp as usize;
+
+            let mut pieces = [[0u8; MAX_PIECES]; 2];
+            let bytes = data.get(pos..pos + material.piece_count)?;
+            for (k, &byte) in bytes.iter().enumerate() {
+                pieces[0][k] = byte & 0xF;
+                pieces[1][k] = byte >> 4;
+            }
+            pos += material.piece_count;
+
+            for side in 0..sides {
+                let mut d = PairsData {
+                    pieces: pieces[side],
+                    ..PairsData::default()
+                };
+                d.set_groups(material, order[side], file);
+                pairs[side].push(d);
+            }
+        }
+
+        pos += pos & 1;
+        for file in 0..files {
+            for side_pairs in pairs[..sides].iter_mut() {
+                pos = side_pairs[file].set_sizes(&data, pos)?;
+            }
+        }
+
+        if kind == Kind::Dtz {
+            /* DTZ values can be remapped per WDL outcome to compress better */
+            for d in pairs[0].iter_mut().filter(|d| d.flags & MAPPED != 0) {
+                if d.flags & WIDE != 0 {
+                    pos += pos & 1;
+                    for map_idx in d.map_idx.iter_mut() {
+                        *map_idx = pos + 2;
+                        pos += 2 * read_le(&data, pos, 2) as usize + 2;
+                    }
+                } else {
+                    for map_idx in d.map_idx.iter_mut() {
+                        *map_idx = pos + 1;
+                        pos += *data.get(pos)? as usize + 1;
+                    }
+                }
+            }
+            pos += pos & 1;
+        }
+
+        for file in 0..files {
+            for side_pairs in pairs[..sides].iter_mut() {
+                let d = &mut side_pairs[file];
+                d.sparse_index = pos;
+                pos += 6 * d.sparse_index_size;
+            }
+        }
+
+        for file in 0..files {
+            for side_pairs in pairs[..sides].iter_mut() {
+                let d = &mut side_pairs[file];
+                d.block_lengths = pos;
+                pos += 2 * d.block_length_size;
+            }
+        }
+
+        for file in 0..files {
+            for side_pairs in pairs[..sides].iter_mut() {
+                let d = &mut side_pairs[file];
+                pos = (pos + 0x3F) & !0x3F;
+                d.data = pos;
+                pos += d.num_blocks * d.block_size;
+            }
+        }
+
+        let truncated = pairs
+            .iter()
+            .flatten()
+            .any(|d| d.num_blocks > 0 && d.data + d.num_blocks * d.block_size > data.len());
+        if truncated {
+            return None;
+        }
+
+        Some(Table { data, pairs })
+    }
+
+    fn get(&self, stm: usize, file: usize) -> &PairsData {
+        let side = if self.pairs[1].is_empty() { 0 } else { stm };
+        let file = min(file, self.pairs[side].len() - 1);
+        &self.pairs[side][file]
+    }
+
+    /* Looks up `board`, which must have the table's material. Returns None if
+     * the DTZ table only has the other side to move.
+     */
+    fn probe(&self, kind: Kind, material: &Material, board: &Board, wdl: WdlScore) -> Option<i32> {
+        let indices = &*INDICES;
+        let black_to_move = board.side_to_move() == Color::Black;
+
+        /* Tables are stored with the stronger side as white, and symmetric ones
+         * only with white to move, so flip the colours and the board otherwise.
+         */
+        let symmetric_black_to_move = material.key == material.key2 && black_to_move;
+        let black_stronger = material_key(&board_counts(board)) != material.key;
+        let flip = symmetric_black_to_move || black_stronger;
+        let flip_color = if flip { 8 } else { 0 };
+        let flip_squares = if flip { 56 } else { 0 };
+        let stm = (flip ^ black_to_move) as usize;
+
+        let mut squares = [0usize; MAX_PIECES];
+        let mut pieces = [0u8; MAX_PIECES];
+        let mut size = 0;
+        let mut lead_pawns = EMPTY;
+        let mut lead_count = 0;
+        let mut file = 0;
+
+        /* Pawn tables are split by the file of the leading pawn, the one nearest
+         * to the edge and then the lowest.
+         */
+        if material.has_pawns {
+            let lead = self.get(0, 0).pieces[0] ^ flip_color;
+            let color = if lead & 8 == 0 {
+                Color::White
+            } else {
+                Color::Black
+            };
+
+            lead_pawns = board.pieces(Piece::Pawn) & board.color_combined(color);
+            for sq in lead_pawns {
+                squares[size] = sq.to_index() ^ flip_squares;
+                size += 1;
+            }
+            lead_count = size;
+
+            let mut first = 0;
+            for i in 1..lead_count {
+                if indices.map_pawns[squares[i]] > indices.map_pawns[squares[first]] {
+                    first = i;
+                }
+            }
+            squares.swap(0, first);
+            file = min(squares[0] & 7, 7 - (squares[0] & 7));
+        }
+
+        if kind == Kind::Dtz {
+            let flags = self.get(stm, file).flags;
+            let symmetric = material.key == material.key2 && !material.has_pawns;
+            if (flags & STM) as usize != stm && !symmetric {
+                return None;
+            }
+        }
+
+        for sq in *board.combined() & !lead_pawns {
+            squares[size] = sq.to_index() ^ flip_squares;
+            pieces[size] = piece_code(board, sq) ^ flip_color;
+            size += 1;
+        }
+
+        /* Order the pieces the way the table lists them. */
+        let d = self.get(stm, file);
+        for i in lead_count..size.saturating_sub(1) {
+            if let Some(j) = (i + 1..size).find(|&j| d.pieces[i] == pieces[j]) {
+                pieces.swap(i, j);
+                squares.swap(i, j);
+            }
+        }
+
+        /* mirror the leading piece onto files a-d */
+        if squares[0] & 7 > 3 {
+            squares[..size].iter_mut().for_each(|sq| *sq ^= 7);
+        }
+
+        let mut idx = if material.has_pawns {
+            let mut idx = indices.lead_pawn_idx[lead_count][squares[0]];
+            squares[1..lead_count].sort_by_key(|&sq| indices.map_pawns[sq]);
+            for (i, &sq) in squares.iter().enumerate().take(lead_count).skip(1) {
+                idx += indices.binomial[i][indices.map_pawns[sq]];
+            }
+            idx
+        } else {
+            /* without pawns, also mirror onto ranks 1-4 and below the diagonal */
+            if squares[0] >> 3 > 3 {
+                squares[..size].iter_mut().for_each(|sq| *sq ^= 56);
+            }
+
+            for i in 0..d.group_len[0] {
+                let off = off_diagonal(squares[i]);
+                if off == 0 {
+                    continue;
+                }
+                if off > 0 {
+                    for sq in squares[i..size].iter_mut() {
+                        *sq = ((*sq >> 3) | (*sq << 3)) & 63;
+                    }
+                }
+                break;
+            }
+
+            encode_leading_pieces(material, &squares)
+        };
+
+        /* The remaining groups, each by its sorted squares, skipping the squares taken before. */
+        idx *= d.group_idx[0];
+        let mut start = d.group_len[0];
+        let mut remaining_pawns = material.has_pawns && material.pawn_count[1] > 0;
+        let mut next = 1;
+
+        while d.group_len[next] != 0 {
+            let len = d.group_len[next];
+            squares[start..start + len].sort_unstable();
+
+            let mut n = 0;
+            for i in 0..len {
+                let sq = squares[start + i];
+                let adjust = squares[..start].iter().filter(|&&s| sq > s).count();
+                let sq = sq.checked_sub(adjust + if remaining_pawns { 8 } else { 0 })?;
+                n += indices.binomial[i + 1][sq];
+            }
+
+            remaining_pawns = false;
+            idx += n * d.group_idx[next];
+            start += len;
+            next += 1;
+        }
+
+        let value = d.decompress(&self.data, idx) as i32;
+
+        Some(match kind {
+            Kind::Wdl => value - 2,
+            Kind::Dtz => self.map_dtz(file, value, wdl),
+        })
+    }
+
+    /* Converts a stored DTZ value to plies, for a position with the given outcome. */
+    fn map_dtz(&self, file: usize, value: i32, wdl: WdlScore) -> i32 {
+        let d = self.get(0, file);
+        let mut value = value;
+
+        if d.flags & MAPPED != 0 {
+            let map = d.map_idx[WDL_MAP[(wdl as i32 + 2) as usize]];
+            value = if d.flags & WIDE != 0 {
+                read_le(&self.data, map + 2 * value as usize, 2) as i32
+            } else {
+                read_le(&self.data, map + value as usize, 1) as i32
+            };
+        }
+
+        let in_moves = match wdl {
+            WdlScore::Win => d.flags & WIN_PLIES == 0,
+            WdlScore::Loss => d.flags & LOSS_PLIES == 0,
+            WdlScore::CursedWin | WdlScore::BlessedLoss => true,
+            WdlScore::Draw => false,
+        };
+        if in_moves {
+            value *= 2;
+        }
+
+        value + 1
+    }
+}
+
+/* The index of the first two or three pieces of a pawnless table. */
+fn encode_leading_pieces(material: &Material, squares: &[usize; MAX_PIECES]) -> u64 {
+    let indices = &*INDICES;
+    let [s0, s1, s2] = [squares[0], squares[1], squares[2]];
+
+    if !material.has_unique_pieces {
+        return indices.map_kk[indices.map_a1d1d4[s0]][s1];
+    }
+
+    let adjust1 = (s1 > s0) as usize;
+    let adjust2 = (s2 > s0) as usize + (s2 > s1) as usize;
+    let rank = |sq: usize| sq >> 3;
+
+    let idx = if off_diagonal(s0) != 0 {
+        (indices.map_a1d1d4[s0] * 63 + (s1 - adjust1)) * 62 + s2 - adjust2
+    } else if off_diagonal(s1) != 0 {
+        (6 * 63 + rank(s0) * 28 + indices.map_b1h1h7[s1]) * 62 + s2 - adjust2
+    } else if off_diagonal(s2) != 0 {
+        6 * 63 * 62
+            + 4 * 28 * 62
+            + rank(s0) * 7 * 28
+            + (rank(s1) - adjust1) * 28
+            + indices.map_b1h1h7[s2]
+    } else {
+        6 * 63 * 62
+            + 4 * 28 * 62
+            + 4 * 7 * 28
+            + rank(s0) * 7 * 6
+            + (rank(s1) - adjust1) * 6
+            + (rank(s2) - adjust2)
+    };
+
+    idx as u64
+}
+
+/* Pieces are numbered the way the tables do, 1 to 6 for white and 9 to 14 for black. */
+fn piece_code(board: &Board, sq: Square) -> u8 {
+    let piece = board
+        .piece_on(sq)
+        .map_or(0, |piece| piece.to_index() as u8 + 1);
+    let color = if board.color_on(sq) == Some(Color::Black) {
+        8
+    } else {
+        0
+    };
+
+    piece | color
+}
+
+/* A table file, mapped the first time it's probed. */
+struct TableFile {
+    path: Option<PathBuf>,
+    table: OnceLock<Option<Table>>,
+}
+
+impl TableFile {
+    fn new(path: Option<PathBuf>) -> Self {
+        TableFile {
+            path,
+            table: OnceLock::new(),
+        }
+    }
+
+    fn get(&self, kind: Kind, material: &Material) -> Option<&Table> {
+        self.table
+            .get_or_init(|| {
+                let path = self.path.as_ref()?;
+                let table = TableData::open(path)
+                    .ok()
+                    .and_then(|data| Table::parse(data, kind, material));
+                if table.is_none() {
+                    info!("Failed to load tablebase {}", path.display());
+                }
+                table
+            })
+            .as_ref()
+    }
+}
+
+struct Entry {
+    material: Material,
+    wdl: TableFile,
+    dtz: TableFile,
+}
+
+/// The Syzygy tablebases found in the `SyzygyPath` directories.
+#[derive(Default)]
+pub struct Tablebases {
+    /* under both colour assignments of the material */
+    entries: RwLock<HashMap<u64, Arc<Entry>>>,
+    max_pieces: AtomicUsize,
+}
+
+fn is_capture(board: &Board, mv: ChessMove) -> bool {
+    board.piece_on(mv.get_dest()).is_some()
+        || (board.piece_on(mv.get_source()) == Some(Piece::Pawn)
+            && mv.get_source().get_file() != mv.get_dest().get_file())
+}
+
+/* The DTZ of a move which resets the fifty-move counter, given the outcome after it. */
+fn dtz_before_zeroing(wdl: WdlScore) -> i32 {
+    match wdl {
+        WdlScore::Win => 1,
+        WdlScore::CursedWin => 101,
+        WdlScore::BlessedLoss => -101,
+        WdlScore::Loss => -1,
+        WdlScore::Draw => 0,
+    }
+}
+
+impl Tablebases {
+    /// Registers the tables in `paths`, a list of directories separated like PATH.
+    /// Returns the number of WDL tables found.
+    pub fn init(&self, paths: &str) -> usize {
+        let mut entries = HashMap::new();
+        let mut max_pieces = 0;
+        let mut found = 0;
+
+        for dir in env::split_paths(paths).filter(|dir| !dir.as_os_str().is_empty()) {
+            let files = match fs::read_dir(&dir) {
+                Ok(files) => files,
+                Err(e) => {
+                    info!("Can't read tablebase directory {}: {}", dir.display(), e);
+                    continue;
+                }
+            };
+
+            for path in files.filter_map(|file| file.ok()).map(|file| file.path()) {
+                if path.extension().and_then(|ext| ext.to_str()) != Some("rtbw") {
+                    continue;
+                }
+                let material = match path
+                    .file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .and_then(Material::from_name)
+                {
+                    Some(material) => material,
+                    None => continue,
+                };
+
+                let dtz = path.with_extension("rtbz");
+                let entry = Arc::new(Entry {
+                    wdl: TableFile::new(Some(path.clone())),
+                    dtz: TableFile::new(Some(dtz).filter(|dtz| dtz.exists())),
+                    material,
+                });
+
+                max_pieces = max_pieces.max(entry.material.piece_count);
+                found += 1;
+                entries.insert(entry.material.key, entry.clone());
+                entries.insert(entry.material.key2, entry);
+            }
+        }
+
+        info!(
+            "Found {} tablebases with up to {} pieces",
+            found, max_pieces
+        );
+        *self.entries.write().unwrap() = entries;
+        self.max_pieces.store(max_pieces, Ordering::Release);
+
+        found
+    }
+
+    /// The most pieces on the board which can be probed.
+    pub fn max_pieces(&self) -> usize {
+        self.max_pieces.load(Ordering::Relaxed)
+    }
+
+    /// Whether `board` can be looked up at all.
+    pub fn covers(&self, board: &Board) -> bool {
+        board.combined().popcnt() as usize <= self.max_pieces()
+            && board.castle_rights(Color::White) == CastleRights::NoRights
+            && board.castle_rights(Color::Black) == CastleRights::NoRights
+    }
+
+    /// The outcome of `board` with best play, ignoring the fifty-move counter of the position.
+    pub fn probe_wdl(&self, board: &Board) -> Option<WdlScore> {
+        if !self.covers(board) {
+            return None;
+        }
+
+        self.search(board, false).map(|(wdl, _)| wdl)
+    }
+
+    /// Plies until the fifty-move counter can be reset with best play, positive if the
+    /// side to move is winning and negative if it's losing. 0 for draws.
+    pub fn probe_dtz(&self, board: &Board) -> Option<i32> {
+        if !self.covers(board) {
+            return None;
+        }
+
+        let (wdl, state) = self.search(board, true)?;
+        if wdl == WdlScore::Draw {
+            return Some(0);
+        }
+        if state == ProbeState::ZeroingBestMove {
+            return Some(dtz_before_zeroing(wdl));
+        }
+
+        if let Some(dtz) = self.probe_table(Kind::Dtz, board, wdl)? {
+            let cursed = wdl == WdlScore::CursedWin || wdl == WdlScore::BlessedLoss;
+            let dtz = dtz + if cursed { 100 } else { 0 };
+            return Some(if wdl > WdlScore::Draw { dtz } else { -dtz });
+        }
+
+        /* The table only has the other side to move, so look one ply ahead
+         * for the quickest way to keep the win, or the slowest loss.
+         */
+        let mut best: Option<i32> = None;
+        for mv in MoveGen::new_legal(board) {
+            let zeroing = position::is_irreversible(board, mv);
+            let child = board.make_move_new(mv);
+
+            let mut dtz = if zeroing {
+                -dtz_before_zeroing(self.search(&child, false)?.0)
+            } else {
+                -self.probe_dtz(&child)?
+            };
+
+            if dtz == 1 && child.status() == BoardStatus::Checkmate {
+                best = Some(1);
+            }
+            if !zeroing {
+                dtz += dtz.signum();
+            }
+
+            let wanted = dtz.signum() == if wdl > WdlScore::Draw { 1 } else { -1 };
+            if wanted && best.is_none_or(|best| dtz < best) {
+                best = Some(dtz);
+            }
+        }
+
+        /* no legal moves means we're mated */
+        Some(best.unwrap_or(-1))
+    }
+
+    /* The raw table lookup. The inner None means the DTZ table has the other side to move. */
+    fn probe_table(&self, kind: Kind, board: &Board, wdl: WdlScore) -> Option<Option<i32>> {
+        /* only the two kings left */
+        if board.combined().popcnt() == 2 {
+            return Some(Some(0));
+        }
+
+        let key = material_key(&board_counts(board));
+        let entry = self.entries.read().unwrap().get(&key).cloned()?;
+        let file = match kind {
+            Kind::Wdl => &entry.wdl,
+            Kind::Dtz => &entry.dtz,
+        };
+        let table = file.get(kind, &entry.material)?;
+
+        Some(table.probe(kind, &entry.material, board, wdl))
+    }
+
+    /* Tables may store anything for positions where a capture (or for DTZ, a pawn
+     * move) wins, and en passant isn't encoded at all, so those moves are searched
+     * and the best of them and the stored value is the real outcome.
+     */
+    fn search(&self, board: &Board, check_zeroing: bool) -> Option<(WdlScore, ProbeState)> {
+        let moves: Vec<ChessMove> = MoveGen::new_legal(board).collect();
+        let mut best = WdlScore::Loss;
+        let mut searched = 0;
+
+        for &mv in moves.iter() {
+            let pawn_move = board.piece_on(mv.get_source()) == Some(Piece::Pawn);
+            let zeroing = is_capture(board, mv) || (check_zeroing && pawn_move);
+            if !zeroing {
+                continue;
+            }
+
+            searched += 1;
+            let value = -self.search(&board.make_move_new(mv), false)?.0;
+            if value > best {
+                best = value;
+                if value == WdlScore::Win {
+                    return Some((value, ProbeState::ZeroingBestMove));
+                }
+            }
+        }
+
+        let no_more_moves = searched > 0 && searched == moves.len();
+        let value = if no_more_moves {
+            best
+        } else {
+            WdlScore::from_value(self.probe_table(Kind::Wdl, board, WdlScore::Draw)??)
+        };
+
+        if best >= value {
+            let state = if best > WdlScore::Draw || no_more_moves {
+                ProbeState::ZeroingBestMove
+            } else {
+                ProbeState::Ok
+            };
+            return Some((best, state));
+        }
+
+        Some((value, ProbeState::Ok))
+    }
+
+    /// Keeps the root moves which preserve the best tablebase outcome. When playing, only
+    /// the winning moves quickest to reset the fifty-move counter are kept, so that won
+    /// endings always make progress. Returns None if the position can't be probed.
+    pub fn filter_root_moves(
+        &self,
+        position: &Position,
+        moves: &[ChessMove],
+        analysis: bool,
+    ) -> Option<Vec<ChessMove>> {
+        let board = &position.board;
+        if !self.covers(board) || moves.is_empty() {
+            return None;
+        }
+
+        let clock = position.halfmove_clock as i32;
+        let mut ranked = Vec::with_capacity(moves.len());
+
+        for &mv in moves {
+            let child = board.make_move_new(mv);
+            let repeated = position.history.contains(&child.get_hash());
+
+            let mut dtz = if position::is_irreversible(board, mv) {
+                dtz_before_zeroing(-self.probe_wdl(&child)?)
+            } else if repeated || clock + 1 >= 100 {
+                0
+            } else {
+                let dtz = -self.probe_dtz(&child)?;
+                dtz + dtz.signum()
+            };
+
+            if dtz == 2 && child.status() == BoardStatus::Checkmate {
+                dtz = 1;
+            }
+
+            /* wins and losses the fifty-move rule doesn't catch up with rank highest and lowest */
+            let outcome = match dtz {
+                d if d > 0 && d + clock <= 99 => 2,
+                d if d > 0 => 1,
+                d if d < 0 && clock - d <= 99 => -2,
+                d if d < 0 => -1,
+                _ => 0,
+            };
+            ranked.push((mv, outcome, -dtz));
+        }
+
+        let &(_, outcome, dtz) = ranked
+            .iter()
+            .max_by_key(|&&(_, outcome, dtz)| (outcome, dtz))?;
+        let exact = !analysis && outcome != 0;
+
+        Some(
+            ranked
+                .iter()
+                .filter(|&&(_, o, d)| o == outcome && (!exact || d == dtz))
+                .map(|&(mv, _, _)| mv)
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_indices() {
+        let indices = &*INDICES;
+
+        let kings = indices.map_kk.iter().flatten().max().unwrap();
+        assert_eq!(*kings, 461);
+        assert_eq!(indices.map_a1d1d4[Square::B1.to_index()], 0);
+        assert_eq!(indices.map_a1d1d4[Square::D4.to_index()], 9);
+        assert_eq!(indices.map_pawns[Square::A2.to_index()], 47);
+        assert_eq!(indices.map_pawns[Square::H2.to_index()], 46);
+        assert_eq!(indices.lead_pawns_size[1], [6, 6, 6, 6]);
+        assert_eq!(indices.binomial[2][10], 45);
+    }
+
+    #[test]
+    fn test_material() {
+        let material = Material::from_name("KRPvKR").unwrap();
+        assert_eq!(material.piece_count, 5);
+        assert!(material.has_pawns);
+        assert!(material.has_unique_pieces);
+        assert_eq!(material.pawn_count, [1, 0]);
+
+        let board = Board::from_str("8/8/4k3/8/3P4/8/1r6/1R2K3 w - - 0 1").unwrap();
+        assert_eq!(material_key(&board_counts(&board)), material.key);
+        let board = Board::from_str("8/8/4k3/3p4/8/8/1r6/1R2K3 w - - 0 1").unwrap();
+        assert_eq!(material_key(&board_counts(&board)), material.key2);
+
+        assert!(Material::from_name("KKvK").is_none());
+        assert!(Material::from_name("KQvX").is_none());
+        assert_eq!(-WdlScore::CursedWin, WdlScore::BlessedLoss);
+    }
+
+    /* The fixtures are written by the gentb example (tests/syzygy/generate.sh
+     * rebuilds them) rather than copied from the published tables, so they store
+     * the same outcomes and distances but aren't byte for byte identical.
+     */
+    const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/syzygy");
+
+    fn fixtures() -> Tablebases {
+        let tb = Tablebases::default();
+        assert_eq!(tb.init(FIXTURES), 6);
+        assert_eq!(tb.max_pieces(), 4);
+        tb
+    }
+
+    fn load(name: &str, kind: Kind) -> (Option<Table>, Material) {
+        let material = Material::from_name(name).unwrap();
+        let ext = match kind {
+            Kind::Wdl => "rtbw",
+            Kind::Dtz => "rtbz",
+        };
+        let path = format!("{}/{}.{}", FIXTURES, name, ext);
+        let data = TableData::open(Path::new(&path)).unwrap();
+        (Table::parse(data, kind, &material), material)
+    }
+
+    fn board(fen: &str) -> Board {
+        Board::from_str(fen).unwrap()
+    }
+
+    #[test]
+    fn test_parse() {
+        let (table, _) = load("KQvK", Kind::Wdl);
+        let table = table.unwrap();
+        assert_eq!((table.pairs[0].len(), table.pairs[1].len()), (1, 1));
+
+        /* DTZ tables only have one side to move */
+        let (table, _) = load("KQvK", Kind::Dtz);
+        assert!(table.unwrap().pairs[1].is_empty());
+
+        let (table, _) = load("KPvK", Kind::Wdl);
+        let table = table.unwrap();
+        assert_eq!((table.pairs[0].len(), table.pairs[1].len()), (4, 4));
+
+        /* a symmetric table stores only the side to move */
+        let (table, _) = load("KNvKN", Kind::Wdl);
+        assert!(table.unwrap().pairs[1].is_empty());
+
+        let data = fs::read(format!("{}/KQvK.rtbw", FIXTURES)).unwrap();
+        let kqk = Material::from_name("KQvK").unwrap();
+        let kpk = Material::from_name("KPvK").unwrap();
+        let owned = |data: &[u8]| TableData::Owned(data.to_vec());
+        assert!(Table::parse(owned(&data), Kind::Wdl, &kqk).is_some());
+        assert!(Table::parse(owned(&data), Kind::Dtz, &kqk).is_none());
+        assert!(Table::parse(owned(&data), Kind::Wdl, &kpk).is_none());
+        assert!(Table::parse(owned(&data[..data.len() - 64]), Kind::Wdl, &kqk).is_none());
+    }
+
+    #[test]
+    fn test_decompress() {
+        let (table, _) = load("KPvK", Kind::Wdl);
+        let table = table.unwrap();
+        let values: Vec<u16> = [0, 1000, 11718, 23435]
+            .iter()
+            .map(|&idx| table.pairs[1][0].decompress(&table.data, idx))
+            .collect();
+        assert_eq!(values, [2, 0, 2, 0]);
+        assert_eq!(table.pairs[0][0].decompress(&table.data, 0), 4);
+        assert_eq!(table.pairs[0][0].decompress(&table.data, 11718), 2);
+
+        /* every win in KNvKN is a mate in one, so the DTZ table is a single value */
+        let (table, _) = load("KNvKN", Kind::Dtz);
+        let table = table.unwrap();
+        assert!(table.pairs[0][0].flags & SINGLE_VALUE != 0);
+        assert_eq!(table.pairs[0][0].decompress(&table.data, 12345), 0);
+    }
+
+    #[test]
+    fn test_map_dtz() {
+        /* mapped, wins in moves */
+        let (table, _) = load("KQvK", Kind::Dtz);
+        let mut table = table.unwrap();
+        assert_eq!(table.map_dtz(0, 0, WdlScore::Win), 1);
+        assert_eq!(table.map_dtz(0, 9, WdlScore::Win), 19);
+
+        /* the same values read as unmapped, with the losses in plies */
+        table.pairs[0][0].flags = LOSS_PLIES;
+        assert_eq!(table.map_dtz(0, 9, WdlScore::Win), 19);
+        assert_eq!(table.map_dtz(0, 1, WdlScore::Loss), 2);
+        assert_eq!(table.map_dtz(0, 16, WdlScore::Loss), 17);
+
+        let (table, _) = load("KRvK", Kind::Dtz);
+        let table = table.unwrap();
+        assert_eq!(table.map_dtz(0, 15, WdlScore::Win), 31);
+
+        /* the quickest wins are pawn moves, which reset the counter and aren't stored */
+        let (table, _) = load("KPvK", Kind::Dtz);
+        let table = table.unwrap();
+        assert_eq!(table.map_dtz(0, 0, WdlScore::Win), 3);
+    }
+
+    /* Known outcomes in KQvK, KRvK and KPvK, which any correct set of those tables
+     * has to give: mates, stalemates, hanging pieces, promotions and the longest
+     * wins (10 moves in KQvK, 16 in KRvK).
+     */
+    fn probe_known(tb: &Tablebases) {
+        let wdl = |fen| tb.probe_wdl(&board(fen));
+        let dtz = |fen| tb.probe_dtz(&board(fen));
+
+        /* KQvK: mate in one, mated, stalemate, the queen hanging, and the longest win */
+        assert_eq!(wdl("k7/8/1K6/8/8/8/8/6Q1 w - - 0 1"), Some(WdlScore::Win));
+        assert_eq!(dtz("k7/8/1K6/8/8/8/8/6Q1 w - - 0 1"), Some(1));
+        assert_eq!(wdl("k7/1Q6/1K6/8/8/8/8/8 b - - 0 1"), Some(WdlScore::Loss));
+        assert_eq!(dtz("k7/1Q6/1K6/8/8/8/8/8 b - - 0 1"), Some(-1));
+        assert_eq!(wdl("k7/8/1Q6/8/8/8/8/K7 b - - 0 1"), Some(WdlScore::Draw));
+        assert_eq!(wdl("8/8/8/8/8/1k6/2Q5/K7 b - - 0 1"), Some(WdlScore::Draw));
+        assert_eq!(dtz("8/8/8/8/8/1k6/2Q5/K7 b - - 0 1"), Some(0));
+        assert_eq!(dtz("7K/6Q1/8/8/8/3k4/8/8 w - - 0 1"), Some(19));
+
+        /* the same with the colours swapped, looked up through the flipped material key */
+        assert_eq!(wdl("K7/8/1k6/8/8/8/8/6q1 b - - 0 1"), Some(WdlScore::Win));
+        assert_eq!(dtz("K7/8/1k6/8/8/8/8/6q1 b - - 0 1"), Some(1));
+        assert_eq!(wdl("K7/1q6/1k6/8/8/8/8/8 w - - 0 1"), Some(WdlScore::Loss));
+
+        /* KRvK, whose DTZ table only has white to move */
+        assert_eq!(dtz("k7/8/1K6/8/8/8/8/7R w - - 0 1"), Some(1));
+        assert_eq!(dtz("7K/8/8/8/8/8/2k5/1R6 w - - 0 1"), Some(31));
+        assert_eq!(wdl("8/8/8/8/8/8/1kR5/7K b - - 0 1"), Some(WdlScore::Draw));
+        assert_eq!(wdl("8/8/8/4k3/8/8/8/R3K3 b - - 0 1"), Some(WdlScore::Loss));
+
+        /* KPvK */
+        assert_eq!(dtz("8/4P3/8/8/8/k7/8/K7 w - - 0 1"), Some(1));
+        assert_eq!(wdl("8/4P3/8/8/8/k7/8/K7 b - - 0 1"), Some(WdlScore::Loss));
+        assert_eq!(dtz("8/4P3/8/8/8/k7/8/K7 b - - 0 1"), Some(-2));
+        assert_eq!(wdl("k7/8/8/8/8/8/P7/7K w - - 0 1"), Some(WdlScore::Draw));
+        assert_eq!(wdl("k7/8/8/8/8/8/P7/7K b - - 0 1"), Some(WdlScore::Draw));
+        assert_eq!(wdl("4k3/8/4K3/4P3/8/8/8/8 w - - 0 1"), Some(WdlScore::Win));
+        assert_eq!(wdl("4k3/8/4K3/4P3/8/8/8/8 b - - 0 1"), Some(WdlScore::Loss));
+        assert_eq!(wdl("8/8/8/8/8/3kP3/8/K7 b - - 0 1"), Some(WdlScore::Draw));
+        assert_eq!(wdl("8/8/8/8/8/8/5kp1/7K w - - 0 1"), Some(WdlScore::Loss));
+    }
+
+    #[test]
+    fn test_probe() {
+        let tb = fixtures();
+        probe_known(&tb);
+        let wdl = |fen| tb.probe_wdl(&board(fen));
+        let dtz = |fen| tb.probe_dtz(&board(fen));
+
+        /* KNvKN, symmetric, so black to move is looked up with the colours swapped */
+        assert_eq!(dtz("7k/5K1n/8/4N3/8/8/8/8 w - - 0 1"), Some(1));
+        assert_eq!(dtz("8/8/8/8/4n3/8/5k1N/7K b - - 0 1"), Some(1));
+        assert_eq!(
+            wdl("8/8/3k4/3n4/8/3N4/3K4/8 w - - 0 1"),
+            Some(WdlScore::Draw)
+        );
+        assert_eq!(
+            wdl("8/8/3k4/3n4/8/3N4/3K4/8 b - - 0 1"),
+            Some(WdlScore::Draw)
+        );
+
+        /* missing tables */
+        assert_eq!(wdl("8/8/3k4/3b4/8/3B4/3K4/8 w - - 0 1"), None);
+        assert_eq!(wdl("8/8/4k3/8/8/2QQ4/3K4/8 w - - 0 1"), None);
+    }
+
+    /* The published tables, which tests/syzygy/published/fetch.sh downloads, to
+     * check the prober against files it wasn't built alongside.
+     */
+    #[test]
+    #[ignore]
+    fn test_probe_published() {
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/syzygy/published");
+        let tb = Tablebases::default();
+        assert_eq!(tb.init(dir), 3, "run tests/syzygy/published/fetch.sh first");
+        probe_known(&tb);
+    }
+
+    #[test]
+    fn test_filter_root_moves() {
+        let tb = fixtures();
+        let filter = |fen, analysis| {
+            let position = position::setup(Some(fen), &[]).unwrap();
+            let moves: Vec<ChessMove> = MoveGen::new_legal(&position.board).collect();
+            let mut kept: Vec<String> = tb
+                .filter_root_moves(&position, &moves, analysis)
+                .unwrap()
+                .iter()
+                .map(ChessMove::to_string)
+                .collect();
+            kept.sort();
+            kept
+        };
+
+        assert_eq!(filter("k7/8/1K6/8/8/8/8/6Q1 w - - 0 1", false), ["g1g8"]);
+        assert_eq!(filter("k7/8/1K6/8/8/8/8/7R w - - 0 1", false), ["h1h8"]);
+        assert_eq!(
+            filter("8/4P3/8/8/8/k7/8/K7 w - - 0 1", false),
+            ["e7e8q", "e7e8r"]
+        );
+
+        /* analysis keeps every winning move, playing only the quickest ones */
+        let all = filter("k7/8/1K6/8/8/8/8/6Q1 w - - 0 1", true);
+        assert!(all.len() > 1 && all.contains(&"g1g8".to_string()));
+        assert!(!all.contains(&"g1a7".to_string()));
+
+        /* only one move saves the draw */
+        assert_eq!(filter("8/8/8/8/8/1k6/2Q5/K7 b - - 0 1", false), ["b3c2"]);
+    }
+}
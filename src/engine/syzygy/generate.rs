@@ -0,0 +1,1305 @@
+/* Solves small endings by retrograde analysis and writes them as Syzygy tables.
+ * This is what builds the fixtures in tests/syzygy (see generate.sh there).
+ *
+ * The writer works from the format's side: it decodes every table index into a
+ * position and stores the solver's value for it, instead of reusing the prober's
+ * encoding. Generating then probes every position back through the prober, so
+ * the two have to agree on the format to get this far.
+ */
+use chess::{
+    get_bishop_moves, get_king_moves, get_knight_moves, get_pawn_attacks, get_rook_moves, BitBoard,
+    Board, Color, Piece, ALL_SQUARES, EMPTY,
+};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+use super::{
+    Material, Tablebases, WdlScore, DTZ_MAGIC, LOSS_PLIES, MAPPED, PIECE_CHARS, SINGLE_VALUE, STM,
+    WDL_MAGIC, WIDE, WIN_PLIES,
+};
+
+/* Four pieces is as far as the solver goes. */
+const MAX_MEN: usize = 4;
+
+/* The order pieces are listed in, kings first. */
+const ORDER: [Piece; 6] = [
+    Piece::King,
+    Piece::Queen,
+    Piece::Rook,
+    Piece::Bishop,
+    Piece::Knight,
+    Piece::Pawn,
+];
+const PROMOTIONS: [Piece; 4] = [Piece::Queen, Piece::Rook, Piece::Bishop, Piece::Knight];
+
+/* Solved values are for the side to move: plies to a win or, negative, to a loss,
+ * counted the way probe_dtz counts them, or 0 for a draw.
+ */
+const UNKNOWN: i16 = i16::MAX;
+const ILLEGAL: i16 = i16::MIN;
+const MATED: i16 = i16::MIN + 1;
+
+/* The layout written to every table. */
+const BLOCK_SIZE: u8 = 6;
+const SPAN: u8 = 10;
+const MAX_BLOCK_VALUES: usize = 1 << 15;
+const MAX_SYMBOLS: usize = 1024;
+const MIN_PAIR_COUNT: u32 = 8;
+
+type Counts = [[u8; 6]; 2];
+
+fn rank_of(piece: Piece) -> usize {
+    ORDER.iter().position(|&p| p == piece).unwrap()
+}
+
+fn is_kings_only(counts: &Counts) -> bool {
+    counts.iter().flatten().sum::<u8>() == 2
+}
+
+#[derive(Clone, Copy)]
+struct Man {
+    color: usize,
+    piece: Piece,
+    sq: usize,
+}
+
+const NO_MAN: Man = Man {
+    color: 0,
+    piece: Piece::King,
+    sq: 0,
+};
+
+#[derive(Clone, Copy)]
+struct Pos {
+    men: [Man; MAX_MEN],
+    count: usize,
+    stm: usize,
+}
+
+fn attacks(man: &Man, occupied: BitBoard) -> BitBoard {
+    let sq = ALL_SQUARES[man.sq];
+    match man.piece {
+        Piece::Pawn => {
+            let color = [Color::White, Color::Black][man.color];
+            get_pawn_attacks(sq, color, !EMPTY)
+        }
+        Piece::Knight => get_knight_moves(sq),
+        Piece::Bishop => get_bishop_moves(sq, occupied),
+        Piece::Rook => get_rook_moves(sq, occupied),
+        Piece::Queen => get_bishop_moves(sq, occupied) | get_rook_moves(sq, occupied),
+        Piece::King => get_king_moves(sq),
+    }
+}
+
+impl Pos {
+    fn men(&self) -> &[Man] {
+        &self.men[..self.count]
+    }
+
+    fn occupied(&self, color: Option<usize>) -> BitBoard {
+        self.men()
+            .iter()
+            .filter(|man| color.is_none_or(|color| man.color == color))
+            .fold(EMPTY, |bb, man| {
+                bb | BitBoard::from_square(ALL_SQUARES[man.sq])
+            })
+    }
+
+    fn counts(&self) -> Counts {
+        let mut counts = [[0; 6]; 2];
+        for man in self.men() {
+            counts[man.color][man.piece.to_index()] += 1;
+        }
+        counts
+    }
+
+    /* Identical pieces may come in any order, the solver indexes them all. */
+    fn sort(&mut self) {
+        self.men[..self.count].sort_by_key(|man| (man.color, rank_of(man.piece)));
+    }
+
+    fn flipped(&self) -> Pos {
+        let mut pos = *self;
+        for man in pos.men[..pos.count].iter_mut() {
+            man.color ^= 1;
+            man.sq ^= 56;
+        }
+        pos.stm ^= 1;
+        pos.sort();
+        pos
+    }
+
+    fn in_check(&self, color: usize) -> bool {
+        let occupied = self.occupied(None);
+        let king = self
+            .men()
+            .iter()
+            .find(|man| man.color == color && man.piece == Piece::King)
+            .unwrap();
+        let king = BitBoard::from_square(ALL_SQUARES[king.sq]);
+        self.men()
+            .iter()
+            .filter(|man| man.color != color)
+            .any(|man| attacks(man, occupied) & king != EMPTY)
+    }
+
+    /* Distinct squares, no pawns on the back ranks, and the side which just moved not in check. */
+    fn is_legal(&self) -> bool {
+        self.occupied(None).popcnt() as usize == self.count
+            && self
+                .men()
+                .iter()
+                .all(|man| man.piece != Piece::Pawn || (1..7).contains(&(man.sq >> 3)))
+            && !self.in_check(self.stm ^ 1)
+    }
+
+    /* Calls `f` with the position after every legal move, and whether the move
+     * resets the fifty-move counter. En passant is left out, as the tables do.
+     */
+    fn for_each_move(&self, mut f: impl FnMut(&Pos, bool)) {
+        let occupied = self.occupied(None);
+        let own = self.occupied(Some(self.stm));
+        let theirs = self.occupied(Some(self.stm ^ 1));
+
+        for i in 0..self.count {
+            let man = self.men[i];
+            if man.color != self.stm {
+                continue;
+            }
+
+            let mut targets = attacks(&man, occupied) & !own;
+            if man.piece == Piece::Pawn {
+                targets &= theirs;
+                let (step, start) = if man.color == 0 { (8, 1) } else { (-8, 6) };
+                let push = |sq: usize| (sq as i32 + step) as usize;
+                let one = push(man.sq);
+                if occupied & BitBoard::from_square(ALL_SQUARES[one]) == EMPTY {
+                    targets |= BitBoard::from_square(ALL_SQUARES[one]);
+                    let two = push(one);
+                    if man.sq >> 3 == start
+                        && occupied & BitBoard::from_square(ALL_SQUARES[two]) == EMPTY
+                    {
+                        targets |= BitBoard::from_square(ALL_SQUARES[two]);
+                    }
+                }
+            }
+
+            for to in targets {
+                let to = to.to_index();
+                let capture = theirs & BitBoard::from_square(ALL_SQUARES[to]) != EMPTY;
+                let zeroing = capture || man.piece == Piece::Pawn;
+                let last_rank = man.piece == Piece::Pawn && (to >> 3 == 0 || to >> 3 == 7);
+
+                let same = [man.piece];
+                let pieces: &[Piece] = if last_rank { &PROMOTIONS } else { &same };
+                for &piece in pieces {
+                    let mut child = *self;
+                    child.men[i] = Man {
+                        piece,
+                        sq: to,
+                        ..man
+                    };
+                    if capture {
+                        let j = (0..child.count)
+                            .find(|&j| j != i && child.men[j].sq == to)
+                            .unwrap();
+                        child.men[j] = child.men[child.count - 1];
+                        child.count -= 1;
+                    }
+                    child.stm ^= 1;
+                    child.sort();
+
+                    if !child.in_check(self.stm) {
+                        f(&child, zeroing);
+                    }
+                }
+            }
+        }
+    }
+
+    fn fen(&self) -> String {
+        let mut board = [None; 64];
+        for man in self.men() {
+            let c = PIECE_CHARS
+                .iter()
+                .find(|&&(_, p)| p == man.piece)
+                .unwrap()
+                .0;
+            board[man.sq] = Some(if man.color == 0 {
+                c
+            } else {
+                c.to_ascii_lowercase()
+            });
+        }
+
+        let mut fen = String::new();
+        for rank in (0..8).rev() {
+            let mut empty = 0;
+            for file in 0..8 {
+                match board[rank * 8 + file] {
+                    Some(c) => {
+                        if empty > 0 {
+                            fen.push_str(&empty.to_string());
+                            empty = 0;
+                        }
+                        fen.push(c);
+                    }
+                    None => empty += 1,
+                }
+            }
+            if empty > 0 {
+                fen.push_str(&empty.to_string());
+            }
+            if rank > 0 {
+                fen.push('/');
+            }
+        }
+
+        fen + if self.stm == 0 {
+            " w - - 0 1"
+        } else {
+            " b - - 0 1"
+        }
+    }
+}
+
+/* Every placement of one material, with either side to move. */
+struct Solution {
+    counts: Counts,
+    men: Vec<Man>,
+    values: Vec<i16>,
+}
+
+impl Solution {
+    fn new(counts: Counts) -> Self {
+        let mut men = Vec::new();
+        for (color, side) in counts.iter().enumerate() {
+            for &piece in ORDER.iter() {
+                for _ in 0..side[piece.to_index()] {
+                    men.push(Man {
+                        color,
+                        piece,
+                        sq: 0,
+                    });
+                }
+            }
+        }
+
+        Solution {
+            counts,
+            values: vec![ILLEGAL; 2 << (6 * men.len())],
+            men,
+        }
+    }
+
+    fn index(&self, pos: &Pos) -> usize {
+        pos.men()
+            .iter()
+            .rev()
+            .fold(pos.stm, |idx, man| (idx << 6) | man.sq)
+    }
+
+    fn position(&self, idx: usize) -> Pos {
+        let mut pos = Pos {
+            men: [NO_MAN; MAX_MEN],
+            count: self.men.len(),
+            stm: idx >> (6 * self.men.len()),
+        };
+        for (i, &man) in self.men.iter().enumerate() {
+            pos.men[i] = Man {
+                sq: (idx >> (6 * i)) & 63,
+                ..man
+            };
+        }
+        pos
+    }
+}
+
+#[derive(Default)]
+struct Solver {
+    solutions: HashMap<Counts, Solution>,
+}
+
+impl Solver {
+    /* The value of `pos`, looked up with the colours swapped if that's how it was solved. */
+    fn value(&self, pos: &Pos, current: Option<&Solution>) -> i16 {
+        if pos.count == 2 {
+            return 0;
+        }
+
+        let find = |counts: Counts| {
+            current
+                .filter(|solution| solution.counts == counts)
+                .or_else(|| self.solutions.get(&counts))
+        };
+        if let Some(solution) = find(pos.counts()) {
+            return solution.values[solution.index(pos)];
+        }
+
+        let pos = pos.flipped();
+        let solution = find(pos.counts()).expect("endings are solved before those leading to them");
+        solution.values[solution.index(&pos)]
+    }
+
+    /* Whether a capture or pawn move wins, so the prober never reads the DTZ table. */
+    fn zeroing_wins(&self, pos: &Pos) -> bool {
+        let mut wins = false;
+        pos.for_each_move(|child, zeroing| {
+            wins |= zeroing && self.value(child, None) < 0;
+        });
+        wins
+    }
+
+    fn solve(&mut self, counts: Counts) -> Result<(), String> {
+        let flipped = [counts[1], counts[0]];
+        if is_kings_only(&counts)
+            || self.solutions.contains_key(&counts)
+            || self.solutions.contains_key(&flipped)
+        {
+            return Ok(());
+        }
+
+        let pawns = Piece::Pawn.to_index();
+        if counts[0][pawns] > 0 && counts[1][pawns] > 0 {
+            return Err("pawns on both sides aren't supported".to_string());
+        }
+
+        /* first everything a capture or a promotion leads to */
+        for color in 0..2 {
+            for piece in 0..Piece::King.to_index() {
+                if counts[color][piece] == 0 {
+                    continue;
+                }
+
+                let mut fewer = counts;
+                fewer[color][piece] -= 1;
+                self.solve(fewer)?;
+
+                if piece == pawns {
+                    for &promotion in PROMOTIONS.iter() {
+                        let mut promoted = fewer;
+                        promoted[color][promotion.to_index()] += 1;
+                        self.solve(promoted)?;
+                        for captured in 0..Piece::King.to_index() {
+                            if promoted[color ^ 1][captured] > 0 {
+                                let mut taken = promoted;
+                                taken[color ^ 1][captured] -= 1;
+                                self.solve(taken)?;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut solution = Solution::new(counts);
+        if solution.men.iter().any(|man| man.piece == Piece::Pawn) {
+            /* Pawn moves only go forward, so solve the most advanced pawns first,
+             * and pushes always lead to positions already solved.
+             */
+            let mut slices: Vec<Vec<u32>> = vec![Vec::new(); 6 * MAX_MEN + 1];
+            for idx in 0..solution.values.len() {
+                let pos = solution.position(idx);
+                let advanced = pos
+                    .men()
+                    .iter()
+                    .filter(|man| man.piece == Piece::Pawn)
+                    .map(|man| {
+                        if man.color == 0 {
+                            man.sq >> 3
+                        } else {
+                            7 - (man.sq >> 3)
+                        }
+                    })
+                    .try_fold(0, |sum, rank| {
+                        (1..7).contains(&rank).then_some(sum + rank - 1)
+                    });
+                if let Some(advanced) = advanced {
+                    slices[advanced].push(idx as u32);
+                }
+            }
+            for slice in slices.iter().rev() {
+                self.solve_slice(&mut solution, slice.iter().map(|&idx| idx as usize))?;
+            }
+        } else {
+            let size = solution.values.len();
+            self.solve_slice(&mut solution, 0..size)?;
+        }
+
+        self.solutions.insert(counts, solution);
+        Ok(())
+    }
+
+    fn solve_slice(
+        &self,
+        solution: &mut Solution,
+        positions: impl Iterator<Item = usize> + Clone,
+    ) -> Result<(), String> {
+        for idx in positions.clone() {
+            let pos = solution.position(idx);
+            if !pos.is_legal() {
+                continue;
+            }
+
+            let mut moves = 0;
+            pos.for_each_move(|_, _| moves += 1);
+            solution.values[idx] = match moves {
+                0 if pos.in_check(pos.stm) => MATED,
+                0 => 0,
+                _ => UNKNOWN,
+            };
+        }
+
+        /* Pass n finds every win in n plies, and losses once all their moves are
+         * known to lose. Stop once no new value could show up anymore.
+         */
+        let mut longest = 0;
+        for n in 1.. {
+            let mut changed = false;
+            for idx in positions.clone() {
+                if solution.values[idx] != UNKNOWN {
+                    continue;
+                }
+
+                let pos = solution.position(idx);
+                if let Some(value) = self.evaluate(solution, &pos, n) {
+                    solution.values[idx] = value;
+                    longest = longest.max(value.abs());
+                    changed = true;
+                }
+            }
+
+            if !changed && n > longest + 1 {
+                break;
+            }
+        }
+
+        for idx in positions {
+            let value = &mut solution.values[idx];
+            if *value == UNKNOWN {
+                *value = 0;
+            }
+        }
+
+        if longest > 100 {
+            return Err("cursed wins and blessed losses aren't supported".to_string());
+        }
+        Ok(())
+    }
+
+    /* The value of `pos` if it wins in at most n plies or is known to lose. */
+    fn evaluate(&self, solution: &Solution, pos: &Pos, n: i16) -> Option<i16> {
+        let mut win = i16::MAX;
+        let mut loss = 0;
+        let mut lost = true;
+
+        pos.for_each_move(|child, zeroing| {
+            let value = self.value(child, Some(solution));
+            match value {
+                UNKNOWN => lost = false,
+                /* mate counts as a single ply, however the mated side is stored */
+                MATED => win = 1,
+                0 => lost = false,
+                _ if zeroing && value < 0 => win = 1,
+                _ if zeroing => loss = loss.max(1),
+                _ if value < 0 => win = win.min(1 - value),
+                _ => loss = loss.max(value + 1),
+            }
+        });
+
+        if win <= n {
+            Some(win)
+        } else if lost {
+            Some(-loss)
+        } else {
+            None
+        }
+    }
+}
+
+fn parse_counts(name: &str) -> Option<Counts> {
+    let mut counts = [[0u8; 6]; 2];
+    for (side, pieces) in name.split('v').enumerate().take(2) {
+        for c in pieces.chars() {
+            let &(_, piece) = PIECE_CHARS.iter().find(|&&(pc, _)| pc == c)?;
+            counts[side][piece.to_index()] += 1;
+        }
+    }
+    Some(counts)
+}
+
+fn binomial(n: usize, k: usize) -> usize {
+    if k > n {
+        return 0;
+    }
+    (0..k).fold(1, |acc, i| acc * (n - i) / (i + 1))
+}
+
+/* Takes the `n`th free square, skipping the taken ones. */
+fn skip_taken(n: usize, taken: &[usize]) -> usize {
+    let mut taken = taken.to_vec();
+    taken.sort_unstable();
+    taken
+        .iter()
+        .fold(n, |sq, &t| if t <= sq { sq + 1 } else { sq })
+}
+
+/* How a table lists its pieces, and the index space that gives. Supports the
+ * pawnless tables with a unique piece, and those with a single pawn.
+ */
+struct Layout {
+    men: Vec<Man>,
+    /* the leading group first */
+    group_len: Vec<usize>,
+    has_pawns: bool,
+}
+
+/* The squares below the a1-h8 diagonal in the a1-d1-d4 triangle, and on the whole board. */
+fn below_diagonal(triangle: bool) -> Vec<usize> {
+    (0..64)
+        .filter(|&sq| (sq >> 3) < (sq & 7) && (!triangle || (sq & 7) < 4))
+        .collect()
+}
+
+impl Layout {
+    fn new(counts: &Counts) -> Result<Layout, String> {
+        let mut solution = Solution::new(*counts);
+        let pawns: Vec<usize> = (0..solution.men.len())
+            .filter(|&i| solution.men[i].piece == Piece::Pawn)
+            .collect();
+        let has_pawns = !pawns.is_empty();
+
+        let lead = if has_pawns {
+            if pawns.len() > 1 {
+                return Err("more than one pawn isn't supported".to_string());
+            }
+            1
+        } else {
+            let unique = counts
+                .iter()
+                .any(|side| side[..Piece::King.to_index()].contains(&1));
+            if !unique {
+                return Err("tables without a unique piece aren't supported".to_string());
+            }
+            /* both kings, then the first other piece */
+            let kings = solution.men.iter().position(|man| man.color == 1).unwrap();
+            let black_king = solution.men.remove(kings);
+            solution.men.insert(1, black_king);
+            3
+        };
+
+        let mut men = solution.men;
+        if has_pawns {
+            let pawn = men.remove(pawns[0]);
+            men.insert(0, pawn);
+        }
+
+        let mut group_len = vec![lead];
+        for i in lead..men.len() {
+            let same =
+                i > lead && men[i].color == men[i - 1].color && men[i].piece == men[i - 1].piece;
+            if same {
+                *group_len.last_mut().unwrap() += 1;
+            } else {
+                group_len.push(1);
+            }
+        }
+
+        Ok(Layout {
+            men,
+            group_len,
+            has_pawns,
+        })
+    }
+
+    fn files(&self) -> usize {
+        if self.has_pawns {
+            4
+        } else {
+            1
+        }
+    }
+
+    fn code(man: &Man) -> u8 {
+        (man.color as u8) * 8 + man.piece.to_index() as u8 + 1
+    }
+
+    fn group_sizes(&self) -> Vec<usize> {
+        let lead = if self.has_pawns { 6 } else { 31332 };
+        let mut free = 64 - self.group_len[0];
+        let mut sizes = vec![lead];
+        for &len in self.group_len[1..].iter() {
+            sizes.push(binomial(free, len));
+            free -= len;
+        }
+        sizes
+    }
+
+    fn size(&self) -> usize {
+        self.group_sizes().iter().product()
+    }
+
+    /* The squares of the listed pieces for a table index, the leading group being
+     * the least significant.
+     */
+    fn decode(&self, file: usize, idx: usize) -> Vec<usize> {
+        let sizes = self.group_sizes();
+        let mut rest = idx / sizes[0];
+        let mut squares = if self.has_pawns {
+            vec![((idx % sizes[0]) + 1) * 8 + file]
+        } else {
+            decode_leading(idx % sizes[0])
+        };
+
+        for (&len, &size) in self.group_len[1..].iter().zip(sizes[1..].iter()) {
+            let mut n = rest % size;
+            rest /= size;
+
+            /* the combinatorial number system, from the highest square down */
+            let mut group = Vec::new();
+            for k in (1..=len).rev() {
+                let mut x = k - 1;
+                while binomial(x + 1, k) <= n {
+                    x += 1;
+                }
+                n -= binomial(x, k);
+                group.push(x);
+            }
+
+            let taken = squares.clone();
+            squares.extend(group.iter().rev().map(|&n| skip_taken(n, &taken)));
+        }
+
+        squares
+    }
+}
+
+/* The three leading pieces of a pawnless table: the first in the a1-d1-d4 triangle,
+ * and the first one off the diagonal below it.
+ */
+fn decode_leading(idx: usize) -> Vec<usize> {
+    let triangle = below_diagonal(true);
+    let below = below_diagonal(false);
+    let diagonal = |rank: usize| rank * 9;
+
+    if idx < 6 * 63 * 62 {
+        let s0 = triangle[idx / (63 * 62)];
+        let s1 = skip_taken((idx / 62) % 63, &[s0]);
+        return vec![s0, s1, skip_taken(idx % 62, &[s0, s1])];
+    }
+
+    let idx = idx - 6 * 63 * 62;
+    if idx < 4 * 28 * 62 {
+        let (s0, s1) = (diagonal(idx / (28 * 62)), below[(idx / 62) % 28]);
+        return vec![s0, s1, skip_taken(idx % 62, &[s0, s1])];
+    }
+
+    let idx = idx - 4 * 28 * 62;
+    if idx < 4 * 7 * 28 {
+        let r0 = idx / (7 * 28);
+        let r1 = skip_taken((idx / 28) % 7, &[r0]);
+        return vec![diagonal(r0), diagonal(r1), below[idx % 28]];
+    }
+
+    let idx = idx - 4 * 7 * 28;
+    let r0 = idx / (7 * 6);
+    let r1 = skip_taken((idx / 6) % 7, &[r0]);
+    let r2 = skip_taken(idx % 6, &[r0, r1]);
+    vec![diagonal(r0), diagonal(r1), diagonal(r2)]
+}
+
+/* One side of one file of a table, ready to be written. */
+struct Packed {
+    flags: u8,
+    /* the value of a single valued table */
+    single: u8,
+    num_blocks: usize,
+    max_len: u8,
+    min_len: u8,
+    lowest: Vec<u16>,
+    btree: Vec<(u16, u16)>,
+    sparse: Vec<(u32, u16)>,
+    block_lengths: Vec<u16>,
+    data: Vec<u8>,
+    /* DTZ values by WDL outcome: wins, losses, cursed wins and blessed losses */
+    maps: Option<[Vec<u16>; 4]>,
+}
+
+impl Packed {
+    fn single(flags: u8, value: u16) -> Packed {
+        Packed {
+            flags: flags | SINGLE_VALUE,
+            single: value as u8,
+            num_blocks: 0,
+            max_len: 0,
+            min_len: 0,
+            lowest: Vec::new(),
+            btree: Vec::new(),
+            sparse: Vec::new(),
+            block_lengths: Vec::new(),
+            data: Vec::new(),
+            maps: None,
+        }
+    }
+
+    /* Positions the prober never reads (None) take the value before them, which
+     * makes for longer runs.
+     */
+    fn new(flags: u8, values: &[Option<u16>]) -> Packed {
+        let first = values.iter().flatten().next().copied().unwrap_or(0);
+        let values: Vec<u16> = values
+            .iter()
+            .scan(first, |last, &value| {
+                *last = value.unwrap_or(*last);
+                Some(*last)
+            })
+            .collect();
+        if values.iter().all(|&value| value == first) {
+            return Packed::single(flags, first);
+        }
+
+        let (btree, lengths, seq) = pair_symbols(&values);
+        let code_lengths = huffman(&seq, btree.len());
+
+        /* canonical numbering: the longest codes get the lowest numbers */
+        let mut numbered: Vec<usize> = (0..btree.len()).collect();
+        numbered.sort_by_key(|&sym| (code_lengths[sym] == 0, Reverse(code_lengths[sym]), sym));
+        let mut number = vec![0u16; btree.len()];
+        for (n, &sym) in numbered.iter().enumerate() {
+            number[sym] = n as u16;
+        }
+        let btree: Vec<(u16, u16)> = numbered
+            .iter()
+            .map(|&sym| match btree[sym] {
+                (value, 0xFFF) => (value, 0xFFF),
+                (left, right) => (number[left as usize], number[right as usize]),
+            })
+            .collect();
+
+        let max_len = *code_lengths.iter().max().unwrap();
+        let min_len = *code_lengths.iter().filter(|&&len| len > 0).min().unwrap();
+        let count = |len: u8| code_lengths.iter().filter(|&&l| l == len).count() as u64;
+        let mut lowest = vec![0u64; (max_len - min_len) as usize + 1];
+        let mut base = vec![0u64; lowest.len()];
+        for i in (0..lowest.len() - 1).rev() {
+            let n = count(min_len + i as u8 + 1);
+            lowest[i] = lowest[i + 1] + n;
+            assert_eq!((base[i + 1] + n) % 2, 0, "Huffman code is complete");
+            base[i] = (base[i + 1] + n) / 2;
+        }
+        let code = |sym: usize| {
+            let len = code_lengths[sym];
+            let i = (len - min_len) as usize;
+            (base[i] + number[sym] as u64 - lowest[i], len)
+        };
+
+        /* fill the blocks with whole symbols */
+        let block_bits = 8 << BLOCK_SIZE;
+        let mut data = Vec::new();
+        let mut block_lengths = Vec::new();
+        let mut block = BitWriter::default();
+        let mut block_values = 0;
+        for &sym in seq.iter() {
+            let (bits, len) = code(sym as usize);
+            let covered = lengths[sym as usize];
+            if block.len + len as usize > block_bits || block_values + covered > MAX_BLOCK_VALUES {
+                data.extend(block.finish(block_bits / 8));
+                block_lengths.push((block_values - 1) as u16);
+                block = BitWriter::default();
+                block_values = 0;
+            }
+            block.push(bits, len);
+            block_values += covered;
+        }
+        data.extend(block.finish(block_bits / 8));
+        block_lengths.push((block_values - 1) as u16);
+
+        /* every entry points at the middle of its span */
+        let span = 1usize << SPAN;
+        let mut sparse = Vec::new();
+        let (mut block, mut start) = (0, 0);
+        for k in 0..values.len().div_ceil(span) {
+            let target = k * span + span / 2;
+            while block + 1 < block_lengths.len() && target > start + block_lengths[block] as usize
+            {
+                start += block_lengths[block] as usize + 1;
+                block += 1;
+            }
+            sparse.push((block as u32, (target - start) as u16));
+        }
+
+        Packed {
+            flags,
+            single: 0,
+            num_blocks: block_lengths.len(),
+            max_len,
+            min_len,
+            lowest: lowest.iter().map(|&n| n as u16).collect(),
+            btree,
+            sparse,
+            block_lengths,
+            data,
+            maps: None,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.data.len() + 6 * self.sparse.len() + 2 * self.block_lengths.len()
+    }
+
+    fn write_sizes(&self, out: &mut Vec<u8>) {
+        out.push(self.flags);
+        if self.flags & SINGLE_VALUE != 0 {
+            out.push(self.single);
+            return;
+        }
+
+        out.extend([BLOCK_SIZE, SPAN, 0]);
+        out.extend((self.num_blocks as u32).to_le_bytes());
+        out.extend([self.max_len, self.min_len]);
+        for &lowest in self.lowest.iter() {
+            out.extend(lowest.to_le_bytes());
+        }
+        out.extend((self.btree.len() as u16).to_le_bytes());
+        for &(left, right) in self.btree.iter() {
+            let lr = left as u32 | (right as u32) << 12;
+            out.extend(&lr.to_le_bytes()[..3]);
+        }
+        if self.btree.len() % 2 == 1 {
+            out.push(0);
+        }
+    }
+}
+
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    len: usize,
+}
+
+impl BitWriter {
+    /* Appends the low `len` bits of `bits`, most significant first. */
+    fn push(&mut self, bits: u64, len: u8) {
+        for i in (0..len).rev() {
+            if self.len.is_multiple_of(8) {
+                self.bytes.push(0);
+            }
+            if bits >> i & 1 != 0 {
+                *self.bytes.last_mut().unwrap() |= 0x80 >> (self.len % 8);
+            }
+            self.len += 1;
+        }
+    }
+
+    fn finish(mut self, size: usize) -> Vec<u8> {
+        self.bytes.resize(size, 0);
+        self.bytes
+    }
+}
+
+/* Recursive pairing: repeatedly replaces the most common pair of neighbouring
+ * symbols with a new one. Returns the symbols, as pairs or leaf values, how many
+ * values each stands for, and the symbols which make up `values`.
+ */
+fn pair_symbols(values: &[u16]) -> (Vec<(u16, u16)>, Vec<usize>, Vec<u16>) {
+    let mut leaves: Vec<u16> = values.to_vec();
+    leaves.sort_unstable();
+    leaves.dedup();
+
+    let mut btree: Vec<(u16, u16)> = leaves.iter().map(|&value| (value, 0xFFF)).collect();
+    let mut lengths = vec![1; btree.len()];
+    let mut seq: Vec<u16> = values
+        .iter()
+        .map(|value| leaves.binary_search(value).unwrap() as u16)
+        .collect();
+
+    let mut counts = vec![0u32; MAX_SYMBOLS * MAX_SYMBOLS];
+    while btree.len() < MAX_SYMBOLS {
+        counts.iter_mut().for_each(|count| *count = 0);
+        for pair in seq.windows(2) {
+            counts[pair[0] as usize * MAX_SYMBOLS + pair[1] as usize] += 1;
+        }
+
+        let best = (0..counts.len())
+            .filter(|&i| counts[i] >= MIN_PAIR_COUNT)
+            .filter(|&i| lengths[i / MAX_SYMBOLS] + lengths[i % MAX_SYMBOLS] <= 256)
+            .max_by_key(|&i| (counts[i], Reverse(i)));
+        let best = match best {
+            Some(best) => best,
+            None => break,
+        };
+
+        let (left, right) = ((best / MAX_SYMBOLS) as u16, (best % MAX_SYMBOLS) as u16);
+        let sym = btree.len() as u16;
+        btree.push((left, right));
+        lengths.push(lengths[left as usize] + lengths[right as usize]);
+
+        let mut paired = Vec::with_capacity(seq.len());
+        let mut i = 0;
+        while i < seq.len() {
+            if i + 1 < seq.len() && seq[i] == left && seq[i + 1] == right {
+                paired.push(sym);
+                i += 2;
+            } else {
+                paired.push(seq[i]);
+                i += 1;
+            }
+        }
+        seq = paired;
+    }
+
+    (btree, lengths, seq)
+}
+
+/* Huffman code lengths for the symbols of `seq`, 0 for those it doesn't use. */
+fn huffman(seq: &[u16], symbols: usize) -> Vec<u8> {
+    let mut freq = vec![0u64; symbols];
+    for &sym in seq {
+        freq[sym as usize] += 1;
+    }
+
+    /* a code needs two symbols, so pad with an unused one */
+    let used: Vec<usize> = (0..symbols).filter(|&sym| freq[sym] > 0).collect();
+    if used.len() == 1 {
+        let other = (used[0] + 1) % symbols;
+        let mut lengths = vec![0; symbols];
+        lengths[used[0]] = 1;
+        lengths[other] = 1;
+        return lengths;
+    }
+
+    let mut parent = vec![usize::MAX; symbols];
+    let mut heap: BinaryHeap<Reverse<(u64, usize)>> =
+        used.iter().map(|&sym| Reverse((freq[sym], sym))).collect();
+    while heap.len() > 1 {
+        let Reverse((f1, n1)) = heap.pop().unwrap();
+        let Reverse((f2, n2)) = heap.pop().unwrap();
+        let node = parent.len();
+        parent.push(usize::MAX);
+        parent[n1] = node;
+        parent[n2] = node;
+        heap.push(Reverse((f1 + f2, node)));
+    }
+
+    let mut lengths = vec![0u8; symbols];
+    for &sym in used.iter() {
+        let mut node = sym;
+        while parent[node] != usize::MAX {
+            node = parent[node];
+            lengths[sym] += 1;
+        }
+        assert!(lengths[sym] <= 32, "Huffman code too long");
+    }
+    lengths
+}
+
+/* The files of a table: for each file of the leading pawn, the sides stored. */
+fn write_table(magic: [u8; 4], split: bool, layout: &Layout, files: &[Vec<Packed>]) -> Vec<u8> {
+    let mut out = magic.to_vec();
+    out.push(split as u8 | (layout.has_pawns as u8) << 1);
+
+    for _ in files {
+        out.push(0);
+        out.extend(layout.men.iter().map(|man| Layout::code(man) * 0x11));
+    }
+    if out.len() % 2 == 1 {
+        out.push(0);
+    }
+
+    for packed in files.iter().flatten() {
+        packed.write_sizes(&mut out);
+    }
+
+    let mut mapped = false;
+    for packed in files.iter().flatten() {
+        if let Some(maps) = &packed.maps {
+            mapped = true;
+            if packed.flags & WIDE != 0 {
+                if out.len() % 2 == 1 {
+                    out.push(0);
+                }
+                for map in maps.iter() {
+                    out.extend((map.len() as u16).to_le_bytes());
+                    map.iter()
+                        .for_each(|&value| out.extend(value.to_le_bytes()));
+                }
+            } else {
+                for map in maps.iter() {
+                    out.push(map.len() as u8);
+                    out.extend(map.iter().map(|&value| value as u8));
+                }
+            }
+        }
+    }
+    if mapped && out.len() % 2 == 1 {
+        out.push(0);
+    }
+
+    for packed in files.iter().flatten() {
+        for &(block, offset) in packed.sparse.iter() {
+            out.extend(block.to_le_bytes());
+            out.extend(offset.to_le_bytes());
+        }
+    }
+    for packed in files.iter().flatten() {
+        packed
+            .block_lengths
+            .iter()
+            .for_each(|&len| out.extend(len.to_le_bytes()));
+    }
+    for packed in files.iter().flatten() {
+        if packed.num_blocks > 0 {
+            out.resize((out.len() + 0x3F) & !0x3F, 0);
+            out.extend(&packed.data);
+        }
+    }
+
+    out
+}
+
+/* The position at a table index, with the listed colours as they are. */
+fn table_position(layout: &Layout, file: usize, idx: usize, stm: usize) -> Pos {
+    let mut pos = Pos {
+        men: [NO_MAN; MAX_MEN],
+        count: layout.men.len(),
+        stm,
+    };
+    for (i, sq) in layout.decode(file, idx).into_iter().enumerate() {
+        pos.men[i] = Man {
+            sq,
+            ..layout.men[i]
+        };
+    }
+    pos.sort();
+    pos
+}
+
+fn wdl_table(solver: &Solver, material: &Material, layout: &Layout) -> Vec<u8> {
+    let sides = if material.key != material.key2 { 2 } else { 1 };
+    let files: Vec<Vec<Packed>> = (0..layout.files())
+        .map(|file| {
+            (0..sides)
+                .map(|stm| {
+                    let values: Vec<Option<u16>> = (0..layout.size())
+                        .map(|idx| {
+                            let pos = table_position(layout, file, idx, stm);
+                            match solver.value(&pos, None) {
+                                ILLEGAL => None,
+                                0 => Some(2),
+                                value if value > 0 => Some(4),
+                                _ => Some(0),
+                            }
+                        })
+                        .collect();
+                    Packed::new(0, &values)
+                })
+                .collect()
+        })
+        .collect();
+
+    write_table(WDL_MAGIC, sides == 2, layout, &files)
+}
+
+/* One file of a DTZ table with `stm` to move. Distances are stored in moves
+ * where they're all odd, and otherwise in plies, and remapped per outcome.
+ */
+fn dtz_file(solver: &Solver, layout: &Layout, file: usize, stm: usize) -> Packed {
+    let distances: Vec<Option<(usize, u16)>> = (0..layout.size())
+        .map(|idx| {
+            let pos = table_position(layout, file, idx, stm);
+            match solver.value(&pos, None) {
+                ILLEGAL | 0 => None,
+                MATED => Some((1, 1)),
+                value if value < 0 => Some((1, -value as u16)),
+                _ if solver.zeroing_wins(&pos) => None,
+                value => Some((0, value as u16)),
+            }
+        })
+        .collect();
+
+    let plies = [0, 1].map(|class| {
+        distances
+            .iter()
+            .flatten()
+            .any(|&(c, dtz)| c == class && dtz % 2 == 0)
+    });
+    let mut flags = if stm == 1 { STM } else { 0 };
+    if plies[0] {
+        flags |= WIN_PLIES;
+    }
+    if plies[1] {
+        flags |= LOSS_PLIES;
+    }
+
+    let stored: Vec<Option<(usize, u16)>> = distances
+        .iter()
+        .map(|d| d.map(|(class, dtz)| (class, if plies[class] { dtz - 1 } else { (dtz - 1) / 2 })))
+        .collect();
+
+    let first = stored
+        .iter()
+        .flatten()
+        .next()
+        .map_or(0, |&(_, value)| value);
+    if stored.iter().flatten().all(|&(_, value)| value == first) {
+        return Packed::single(flags, first);
+    }
+
+    let mut maps: [Vec<u16>; 4] = Default::default();
+    for &(class, value) in stored.iter().flatten() {
+        maps[class].push(value);
+    }
+    for map in maps.iter_mut() {
+        map.sort_unstable();
+        map.dedup();
+    }
+    if maps.iter().flatten().any(|&value| value > 255) {
+        flags |= WIDE;
+    }
+
+    /* a map needs more than one symbol to point into */
+    let values: Vec<Option<u16>> = stored
+        .iter()
+        .map(|d| d.map(|(class, value)| maps[class].binary_search(&value).unwrap() as u16))
+        .collect();
+    if values.iter().flatten().any(|&value| value != 0) {
+        let mut packed = Packed::new(flags | MAPPED, &values);
+        packed.maps = Some(maps);
+        packed
+    } else {
+        let values: Vec<Option<u16>> = stored.iter().map(|d| d.map(|(_, value)| value)).collect();
+        Packed::new(flags, &values)
+    }
+}
+
+/* The DTZ table has one side to move, whichever packs smaller. Symmetric tables
+ * are always probed with white to move.
+ */
+fn dtz_table(solver: &Solver, material: &Material, layout: &Layout) -> Vec<u8> {
+    let sides = if material.key != material.key2 { 2 } else { 1 };
+    let files = (0..sides)
+        .map(|stm| {
+            (0..layout.files())
+                .map(|file| vec![dtz_file(solver, layout, file, stm)])
+                .collect::<Vec<_>>()
+        })
+        .min_by_key(|files| files.iter().flatten().map(Packed::len).sum::<usize>())
+        .unwrap();
+
+    write_table(DTZ_MAGIC, false, layout, &files)
+}
+
+/* Probes every position (or a sample of the bigger endings) back through the prober. */
+fn verify(solver: &Solver, tb: &Tablebases, counts: &Counts) -> Result<(), String> {
+    let solution = &solver.solutions[counts];
+    let step = (solution.values.len() / 1_000_000).max(1) | 1;
+
+    for idx in (0..solution.values.len()).step_by(step) {
+        let value = solution.values[idx];
+        if value == ILLEGAL {
+            continue;
+        }
+
+        let pos = solution.position(idx);
+        let fen = pos.fen();
+        let board = Board::from_str(&fen).map_err(|e| format!("{}: {}", fen, e))?;
+        let (wdl, dtz) = match value {
+            MATED => (WdlScore::Loss, -1),
+            0 => (WdlScore::Draw, 0),
+            _ if value > 0 => (WdlScore::Win, value as i32),
+            _ => (WdlScore::Loss, value as i32),
+        };
+
+        let probed = (tb.probe_wdl(&board), tb.probe_dtz(&board));
+        if probed != (Some(wdl), Some(dtz)) {
+            return Err(format!(
+                "{}: solved {:?} {}, probed {:?} {:?}",
+                fen, wdl, dtz, probed.0, probed.1
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Solves the endings named in `names`, like KRvK, and writes their WDL and DTZ
+/// tables to `dir`, then checks every table by probing it.
+pub fn generate(dir: &Path, names: &[String]) -> io::Result<()> {
+    let invalid = |e: String| io::Error::new(io::ErrorKind::InvalidInput, e);
+    let mut solver = Solver::default();
+
+    let mut tables = Vec::new();
+    for name in names {
+        let material = Material::from_name(name)
+            .filter(|material| material.piece_count <= MAX_MEN)
+            .ok_or_else(|| {
+                invalid(format!(
+                    "{}: not an ending of up to {} pieces",
+                    name, MAX_MEN
+                ))
+            })?;
+        let counts = parse_counts(name).unwrap();
+        let layout = Layout::new(&counts).map_err(|e| invalid(format!("{}: {}", name, e)))?;
+
+        println!("Solving {}...", name);
+        solver
+            .solve(counts)
+            .map_err(|e| invalid(format!("{}: {}", name, e)))?;
+
+        println!("Writing {}...", name);
+        fs::write(
+            dir.join(format!("{}.rtbw", name)),
+            wdl_table(&solver, &material, &layout),
+        )?;
+        fs::write(
+            dir.join(format!("{}.rtbz", name)),
+            dtz_table(&solver, &material, &layout),
+        )?;
+        tables.push((name, counts));
+    }
+
+    let tb = Tablebases::default();
+    tb.init(&dir.to_string_lossy());
+    for (name, counts) in tables {
+        println!("Checking {}...", name);
+        let counts = if solver.solutions.contains_key(&counts) {
+            counts
+        } else {
+            [counts[1], counts[0]]
+        };
+        verify(&solver, &tb, &counts).map_err(|e| invalid(format!("{}: {}", name, e)))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::engine::syzygy::{encode_leading_pieces, INDICES, MAX_PIECES};
+
+    #[test]
+    fn test_decode() {
+        /* every leading index decodes to a position which encodes back to it */
+        let material = Material::from_name("KQvK").unwrap();
+        for idx in 0..31332 {
+            let mut squares = [0; MAX_PIECES];
+            squares[..3].copy_from_slice(&decode_leading(idx));
+            assert_eq!(encode_leading_pieces(&material, &squares), idx as u64);
+        }
+
+        /* the last piece of KNvKN skips the three leading ones */
+        let layout = Layout::new(&parse_counts("KNvKN").unwrap()).unwrap();
+        assert_eq!(layout.group_len, [3, 1]);
+        assert_eq!(layout.size(), 31332 * 61);
+        for n in [0, 1, 30, 60] {
+            let squares = layout.decode(0, n * 31332);
+            let adjust = squares[..3].iter().filter(|&&sq| sq < squares[3]).count();
+            assert_eq!(INDICES.binomial[1][squares[3] - adjust], n as u64);
+        }
+    }
+}
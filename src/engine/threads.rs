@@ -1,37 +1,51 @@
 use super::search;
+use super::search::{NodeCounters, SearchContext};
 use crate::engine::eval::Eval;
+use crate::engine::options;
+use crate::engine::position::Position;
+use crate::engine::skill::Skill;
+use crate::engine::syzygy::TB;
+use crate::engine::timeman::TimeLimits;
 use crate::engine::ttable::{EntryType, EvalMove, TT};
+use crate::engine::wdl::{self, Wdl};
 use chess::{Board, BoardStatus, ChessMove, MoveGen};
 use log::info;
-use num_cpus;
-use std::cell::{Cell, Ref, RefCell};
 use std::cmp::{max, min, Reverse};
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::convert::TryInto;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, RwLock, RwLockReadGuard};
 use std::thread;
 use std::thread::JoinHandle;
 
 use std::collections::HashMap;
 use std::sync::mpsc::SyncSender;
+use std::time::{Duration, Instant};
 use vampirc_uci::{UciInfoAttribute, UciMessage};
 
+use crate::engine::{debug_enabled, EngineMessage, SearchResult};
+
 pub struct _WorkerThread {
     pub root_data: Mutex<RootData>,
+    nodes: Arc<AtomicU64>,
     lock: Mutex<bool>,
     cv: Condvar,
-    tx: SyncSender<UciMessage>,
+    tx: SyncSender<EngineMessage>,
     exit: AtomicBool,
     searching: AtomicBool,
+    /* work other than a search to run when woken up */
+    job: Mutex<Option<Job>>,
     is_main: bool,
 }
 
+type Job = Box<dyn FnOnce() + Send>;
+
 pub struct Worker {
     pub data: WorkerThread,
     pub handle: JoinHandle<()>,
 }
 pub type WorkerThread = Arc<_WorkerThread>;
 impl Worker {
-    pub fn new(is_main: bool, tx: SyncSender<UciMessage>) -> Self {
+    pub fn new(is_main: bool, tx: SyncSender<EngineMessage>) -> Self {
         let data = _WorkerThread::new(is_main, tx);
         let arc = data.clone();
         let handle = thread::spawn(move || {
@@ -42,46 +56,136 @@ impl Worker {
     }
 }
 
+/// Constraints on a single search, as given by the `go` command and the engine options.
+#[derive(Clone, Default, Debug)]
+pub struct SearchLimits {
+    pub depth: Option<u8>,
+    /// Only these root moves are searched. All legal moves are searched if it's empty.
+    pub search_moves: Vec<ChessMove>,
+    pub nodes: Option<u64>,
+    /// Search for a mate in at most this many moves.
+    pub mate: Option<u8>,
+    /// The number of best lines to search with exact scores.
+    pub multipv: usize,
+    /// Handicaps from UCI_LimitStrength, UCI_Elo and Skill Level.
+    pub skill: Skill,
+    /// Report win/draw/loss statistics along with the score.
+    pub show_wdl: bool,
+    pub time: Option<TimeLimits>,
+    /// The search starts out pondering, so the time limits don't apply until a ponderhit.
+    pub ponder: bool,
+    /// Keep searching until told to stop, even once the other limits are reached.
+    pub infinite: bool,
+    /// How much worse than equality a draw is for the engine, in centipawns.
+    pub contempt: Eval,
+    /// UCI_AnalyseMode is on, so heuristics meant for playing games are off.
+    pub analysis: bool,
+}
+
+/// A root move along with its score and principal variation from the last iteration.
+#[derive(Clone, Debug)]
+pub struct RootMove {
+    pub mv: ChessMove,
+    pub eval: Eval,
+    pub pv: Vec<ChessMove>,
+}
+
+impl RootMove {
+    fn new(mv: ChessMove) -> Self {
+        RootMove {
+            mv,
+            eval: -search::SCORE_INF,
+            pv: Vec::new(),
+        }
+    }
+}
+
+/// The outcome of a completed iteration, kept to judge how settled the search is.
+#[derive(Clone, Copy, Debug)]
+struct Iteration {
+    best_move: ChessMove,
+    eval: Eval,
+    /* whether the aspiration window failed low on the way */
+    failed_low: bool,
+}
+
 #[derive(Default)]
 pub struct RootData {
     board: Board,
-    moves: Vec<EvalMove>,
+    limits: SearchLimits,
+    ctx: SearchContext,
+    /* only the main thread reports to the engine controller */
+    reporter: Option<SyncSender<EngineMessage>>,
+    moves: Vec<RootMove>,
     pv: Vec<ChessMove>,
     best_move: Option<EvalMove>,
     root_depth: u8,
     completed_depth: u8,
+    history: Vec<Iteration>,
+    /* the root moves as ordered by the last completed iteration, for the skill handicap */
+    completed_moves: Vec<RootMove>,
+    /* when the last info line was sent, relative to the start of the search */
+    last_report: Duration,
 }
 
 impl RootData {
+    pub fn new(ctx: SearchContext, reporter: Option<SyncSender<EngineMessage>>) -> Self {
+        RootData {
+            ctx,
+            reporter,
+            ..RootData::default()
+        }
+    }
+
     pub fn clear(&mut self) {
-        // self.moves.clear();
-        // self.pv.clear();
-        self.root_depth = 0;
+        self.moves.clear();
+        self.pv.clear();
+        self.best_move = None;
+        self.root_depth = 1;
         self.completed_depth = 0;
+        self.history.clear();
+        self.completed_moves.clear();
+        self.last_report = Duration::default();
     }
 
-    pub fn populate(&mut self, board: &Board) {
+    pub fn populate(&mut self, position: &Position, limits: &SearchLimits, nodes: &NodeCounters) {
+        let board = &position.board;
         self.clear();
         self.board = *board;
-        self.moves
-            .extend(MoveGen::new_legal(board).map(|mv| EvalMove {
-                mv,
-                eval: -search::SCORE_INF,
-            }));
+        self.limits = limits.clone();
+        self.ctx.reset(position, limits);
+        self.ctx.share_nodes(nodes.clone());
+        let search_moves = &self.limits.search_moves;
+        self.moves.extend(
+            MoveGen::new_legal(board)
+                .filter(|mv| search_moves.is_empty() || search_moves.contains(mv))
+                .map(RootMove::new),
+        );
+    }
+
+    /// The number of lines to search with exact scores.
+    fn multipv(&self) -> usize {
+        self.limits.multipv.clamp(1, max(self.moves.len(), 1))
+    }
+
+    fn sort_moves(&mut self) {
+        self.moves.sort_by_key(|rm| Reverse(rm.eval));
     }
 
     pub fn root_search(&mut self, mut alpha: Eval, mut beta: Eval, depth: u8) -> Eval {
         match self.board.status() {
             BoardStatus::Checkmate => {
-                return -search::SCORE_MATE;
+                return search::mated_in(0);
             }
             BoardStatus::Stalemate => {
-                return 0;
+                return self.ctx.draw_score(&self.board);
             }
             _ => {}
         }
 
-        let mating_score = search::SCORE_MATE;
+        self.ctx.visit_node(0);
+
+        let mating_score = search::mate_in(0);
 
         let mut max = -search::SCORE_INF;
 
@@ -91,22 +195,49 @@ impl RootData {
         let mut tt_depth: u8 = 0;
         let mut tt_eval: Eval = -search::SCORE_INF;
 
-        if let Some(te) = table_entry {
+        /* bounds aren't good enough to settle the root with */
+        if let Some(te) = table_entry.filter(|te| te.entry_type() == EntryType::Pv) {
             tt_move = Some(te.mv.into());
             tt_depth = te.depth;
             tt_eval = te.eval;
         }
 
-        for em in self.moves.iter_mut() {
-            if THREADS.stopped() {
+        let report_currmove = THREADS.elapsed() >= CURRMOVE_DELAY;
+        let multipv = self.multipv();
+        /* the scores of the best lines found so far, best first */
+        let mut best_scores: Vec<Eval> = Vec::with_capacity(multipv + 1);
+
+        for (idx, rm) in self.moves.iter_mut().enumerate() {
+            if self.ctx.stopped() {
                 return 0;
             }
 
-            let EvalMove { mv, eval } = em;
-            let pos = if let Some(new_pos) = search::make_move_new(&self.board, *mv).take() {
+            if let Some(tx) = self.reporter.as_ref() {
+                if report_currmove {
+                    let _ = tx.send(
+                        UciMessage::Info(vec![
+                            UciInfoAttribute::Depth(depth),
+                            UciInfoAttribute::CurrMove(rm.mv),
+                            UciInfoAttribute::CurrMoveNum(idx as u16 + 1),
+                        ])
+                        .into(),
+                    );
+                }
+
+                /* long iterations still let the GUI know the search is progressing */
+                let elapsed = THREADS.elapsed();
+                if elapsed >= self.last_report + INFO_INTERVAL {
+                    self.last_report = elapsed;
+                    let stats = search_stats(depth, &self.ctx);
+                    let _ = tx.send(make_stats_message(&stats).into());
+                }
+            }
+
+            let mv = rm.mv;
+            let pos = if let Some(new_pos) = search::make_move_new(&self.board, mv).take() {
                 new_pos
             } else {
-                if tt_move == Some(*mv) {
+                if tt_move == Some(mv) {
                     info!("Attempted move came from the TT");
                 } else {
                     info!("Attempted move did not come from the TT");
@@ -114,47 +245,64 @@ impl RootData {
                 continue;
             };
 
-            /* If it's the principal variation, do a full search.
-             * Otherwise, do a null window search to see if
-             * an improvement is possible.
-             * If the position is previously unseen, do a regular alpha/beta search.
+            /* In MultiPV mode a move only has to beat the worst of the
+             * best lines so far to get an exact score.
              */
+            let move_alpha = if multipv > 1 {
+                best_scores
+                    .get(multipv - 1)
+                    .map_or(alpha, |&worst| alpha.max(worst))
+            } else {
+                alpha
+            };
 
-            let value = if tt_move == Some(*mv) && tt_depth >= depth {
-                tt_eval
+            /* the TT doesn't know how we got here, so check for draws first */
+            self.ctx.push_move(&self.board, mv);
+            let (value, child_pv) = if self.ctx.is_draw(&pos) {
+                (-self.ctx.draw_score(&pos), Vec::new())
+            } else if tt_move == Some(mv) && tt_depth >= depth {
+                (tt_eval, search::tt_line(&pos, depth - 1))
             } else {
-                -search::alphabeta(pos, -beta, -alpha, depth - 1, 1)
+                let value =
+                    -search::alphabeta(&mut self.ctx, pos, -beta, -move_alpha, depth - 1, 1);
+                (value, self.ctx.pv(1).to_vec())
             };
+            self.ctx.pop_move();
 
             assert!(value > -search::SCORE_INF && value < search::SCORE_INF);
 
-            if THREADS.stopped() {
+            if self.ctx.stopped() {
                 return 0;
             }
-            if value > alpha {
-                *eval = value;
+            if value > move_alpha {
+                rm.eval = value;
+                rm.pv.clear();
+                rm.pv.push(mv);
+                rm.pv.extend(child_pv);
+
+                let pos = best_scores.partition_point(|&score| score >= value);
+                best_scores.insert(pos, value);
+                best_scores.truncate(multipv);
             } else {
-                *eval = -search::SCORE_INF;
+                rm.eval = -search::SCORE_INF;
             }
 
             if value >= beta {
-                TT.save(handle, &self.board, *mv, value, depth, EntryType::Cut);
+                self.pv = rm.pv.clone();
+                TT.save(handle, &self.board, mv, value, depth, EntryType::Cut);
                 return value;
                 //return search::quiesce(board, alpha, beta);
             }
 
             if value > max {
                 max = value;
-                best_move = Some(EvalMove::new(*mv, value));
+                best_move = Some(EvalMove::new(mv, value));
                 if value > alpha {
-                    if value < beta {
+                    self.pv = rm.pv.clone();
+                    if multipv == 1 {
                         alpha = value;
-                    } else {
-                        break;
                     }
                 }
-            } else {
-                *eval = -search::SCORE_INF;
             }
 
             //mate pruning
@@ -183,29 +331,180 @@ impl RootData {
             TT.save(handle, &self.board, mv, eval, depth, entry_type);
         }
 
-        if max >= search::SCORE_MATE - depth as Eval {
-            max - 1
-        } else if max < -search::SCORE_MATE + depth as Eval {
-            max + 1
+        max
+    }
+
+    /// Reports the current best line to the engine controller.
+    /// `bound` tells whether the score is exact or came from an aspiration window failure.
+    fn send_info(&mut self, eval: Eval, bound: EntryType) {
+        let tx = match self.reporter.as_ref() {
+            Some(tx) => tx,
+            None => return,
+        };
+
+        let stats = search_stats(self.root_depth, &self.ctx);
+        self.last_report = stats.time;
+
+        let multipv = self.multipv();
+        if multipv == 1 {
+            let best_move = EvalMove::new(self.moves[0].mv, eval);
+            let wdl = self.wdl(eval);
+            let message = make_info_message(best_move, &self.pv, bound, wdl, &stats, None);
+            let _ = tx.send(message.into());
+            return;
+        }
+
+        /* lines which failed low don't have an exact score to report */
+        for (idx, rm) in self.moves[..multipv]
+            .iter()
+            .enumerate()
+            .filter(|(_, rm)| rm.eval > -search::SCORE_INF)
+        {
+            let line = EvalMove::new(rm.mv, rm.eval);
+            let multipv = Some(idx as u16 + 1);
+            let wdl = self.wdl(rm.eval);
+            let message = make_info_message(line, &rm.pv, bound, wdl, &stats, multipv);
+            let _ = tx.send(message.into());
+        }
+    }
+
+    fn wdl(&self, eval: Eval) -> Option<Wdl> {
+        if self.limits.show_wdl {
+            Some(wdl::MODEL.wdl(eval, &self.board))
         } else {
-            max
+            None
         }
     }
+
+    /// Whether the search has satisfied the `go depth` or `go mate` limits.
+    fn limits_reached(&self) -> bool {
+        let depth = self.completed_depth;
+
+        if let Some(limit) = self.limits.depth {
+            if depth >= limit {
+                info!("Depth limit reached.");
+                return true;
+            }
+        }
+
+        if let Some(moves) = self.limits.mate {
+            let mate_found = self
+                .best_move
+                .and_then(|EvalMove { eval, .. }| search::moves_to_mate(eval))
+                .is_some_and(|n| n > 0 && n as u8 <= moves);
+
+            /* a mate in n moves takes 2n - 1 plies */
+            if mate_found {
+                info!("Mate in {} moves or less found.", moves);
+                return true;
+            } else if depth as u16 >= 2 * moves as u16 - 1 {
+                info!("No mate in {} moves exists.", moves);
+                return true;
+            }
+        }
+
+        false
+    }
+
+    /// Whether it's time to stop instead of starting another iteration.
+    /// The soft time limit is scaled by how settled the search looks, but never beyond the hard one.
+    fn out_of_time(&self) -> bool {
+        let time = match self.limits.time {
+            Some(time) if !THREADS.pondering() => time,
+            _ => return false,
+        };
+
+        /* analysis takes all the time it's given */
+        let scale = if self.limits.analysis {
+            self.time_scale().max(1.0)
+        } else {
+            self.time_scale()
+        };
+
+        let target = min(time.soft.mul_f64(scale), time.hard);
+        if THREADS.timed_elapsed() >= target {
+            self.debug(format!("time target {} ms reached", target.as_millis()));
+            true
+        } else {
+            false
+        }
+    }
+
+    fn debug(&self, s: String) {
+        if let Some(tx) = self.reporter.as_ref() {
+            debug_report(tx, s);
+        }
+    }
+
+    /* How much of the soft time limit to use, judging by the last few iterations. */
+    fn time_scale(&self) -> f64 {
+        let last = match self.history.last() {
+            Some(last) => last,
+            None => return 1.0,
+        };
+
+        /* the longer the best move has stayed the same, the less likely it is to change */
+        let stable = self
+            .history
+            .iter()
+            .rev()
+            .skip(1)
+            .take_while(|it| it.best_move == last.best_move)
+            .count();
+        let stability = 1.3 - 0.1 * min(stable, 6) as f64;
+
+        let recent = &self.history[self.history.len().saturating_sub(5)..];
+        let changes = recent
+            .windows(2)
+            .filter(|w| w[0].best_move != w[1].best_move)
+            .count();
+        let instability = 1.0 + 0.15 * changes as f64;
+
+        let fail_low = if last.failed_low { 1.4 } else { 1.0 };
+
+        let drop = match self.history.iter().rev().nth(1) {
+            Some(prev) => (prev.eval as i32 - last.eval as i32).clamp(0, 100),
+            None => 0,
+        };
+        let score_drop = 1.0 + drop as f64 / 200.0;
+
+        stability * instability * fail_low * score_drop
+    }
 }
 
 impl _WorkerThread {
-    pub fn new(is_main: bool, tx: SyncSender<UciMessage>) -> WorkerThread {
+    pub fn new(is_main: bool, tx: SyncSender<EngineMessage>) -> WorkerThread {
+        let nodes = Arc::new(AtomicU64::new(0));
+        let ctx = SearchContext::new(is_main, nodes.clone());
+        let reporter = if is_main { Some(tx.clone()) } else { None };
+
         Arc::new(_WorkerThread {
-            root_data: Mutex::default(),
+            root_data: Mutex::new(RootData::new(ctx, reporter)),
+            nodes,
             lock: Mutex::new(false),
             cv: Condvar::new(),
             tx,
             exit: AtomicBool::new(false),
             searching: AtomicBool::new(true),
+            job: Mutex::new(None),
             is_main,
         })
     }
 
+    pub fn start_search(&self) {
+        let _lock = self.lock.lock().unwrap();
+        self.searching.store(true, Ordering::Release);
+        self.cv.notify_one();
+    }
+
+    pub fn wait(&self) {
+        let lock = self.lock.lock().unwrap();
+        let _lock = self
+            .cv
+            .wait_while(lock, |_| self.searching.load(Ordering::Acquire))
+            .unwrap();
+    }
+
     pub fn idle(&self) {
         loop {
             info!("Entering idle loop...");
@@ -224,28 +523,70 @@ impl _WorkerThread {
                 }
             }
 
+            let job = self.job.lock().unwrap().take();
+            if let Some(job) = job {
+                info!("Woken up, running job...");
+                job();
+                continue;
+            }
+
             {
                 info!("Woken up, starting search...");
                 let lock = self.root_data.lock().unwrap();
                 self.search(lock);
             }
             if self.is_main {
-                info!("Electing best move...");
-                let best_move = THREADS.elect_best_move();
+                let (skill_move, fallback) = {
+                    let data = self.root_data.lock().unwrap();
+                    /* the node limit usually stops the search mid-iteration, so pick from the
+                     * scores of the last completed one */
+                    let skill_move = if data.limits.skill.enabled() {
+                        data.limits.skill.pick_move(&data.completed_moves)
+                    } else {
+                        None
+                    };
+                    if let Some(mv) = skill_move {
+                        debug_report(&self.tx, format!("skill handicap picked {}", mv));
+                    }
+                    /* in case the search was stopped before any iteration was completed */
+                    (skill_move, data.moves.first().map(|rm| rm.mv))
+                };
+                let best_move = skill_move
+                    .or_else(|| {
+                        info!("Electing best move...");
+                        THREADS.elect_best_move()
+                    })
+                    .or(fallback);
+                let ponder = best_move.and_then(|best_move| {
+                    let data = self.root_data.lock().unwrap();
+                    match data.pv.as_slice() {
+                        [mv, reply, ..] if *mv == best_move => Some(*reply),
+                        _ => search::ponder_move(&data.board, best_move),
+                    }
+                });
 
+                let (hashfull, capacity) = (TT.hashfull(), TT.capacity());
+                let tt_stats = format!("tt hashfull {} of {} entries", hashfull, capacity);
+                debug_report(&self.tx, tt_stats);
                 info!(
-                    "sending final best move({}) to engine controller...",
+                    "sending final best move({:?}) to engine controller...",
                     best_move
                 );
-                self.tx.send(UciMessage::best_move(best_move));
+                let result = SearchResult { best_move, ponder };
+                let _ = self.tx.send(EngineMessage::SearchDone(result));
             }
         }
     }
 
     pub fn search(&self, mut data: MutexGuard<RootData>) {
         if self.is_main {
-            info!("Waking slave threads...");
-            if !data.moves.is_empty() {
+            if data.moves.is_empty() {
+                info!("No legal moves to search.");
+                if !data.limits.infinite {
+                    THREADS.stop();
+                }
+            } else {
+                info!("Waking slave threads...");
                 THREADS.start_search();
             }
         }
@@ -257,33 +598,40 @@ impl _WorkerThread {
 
         let mut depth = data.root_depth;
         let mut failed_high_count: u8 = 0;
+        let mut failed_low = false;
 
-        while depth < 255 && !THREADS.stopped() {
-            if depth >= 4 {
+        while depth < 255 && !data.moves.is_empty() && !data.ctx.stopped() {
+            if depth >= 4 && data.multipv() == 1 {
                 let prev = data
                     .best_move
-                    .map(|EvalMove { mv, eval }| eval)
+                    .map(|EvalMove { eval, .. }| eval)
                     .unwrap_or(data.moves[0].eval);
-                delta = 17 + prev * (prev / 16384);
+                delta = min(
+                    17 + prev as i32 * prev as i32 / 16384,
+                    search::SCORE_INF as i32,
+                ) as Eval;
                 alpha = max(prev.saturating_sub(delta), -search::SCORE_INF);
-                beta = min(prev + delta, search::SCORE_INF);
+                beta = min(prev.saturating_add(delta), search::SCORE_INF);
             }
 
             loop {
                 let adj_depth = max(1, depth.saturating_sub(failed_high_count));
                 best_value = data.root_search(alpha, beta, adj_depth);
 
-                data.moves.sort_by_key(|&em| Reverse(em));
+                data.sort_moves();
 
-                if THREADS.stopped() {
+                if data.ctx.stopped() {
                     break;
                 }
 
                 if best_value <= alpha {
-                    beta = alpha + beta / 2;
+                    data.send_info(best_value, EntryType::All);
+                    beta = ((alpha as i32 + beta as i32) / 2) as Eval;
                     alpha = max(best_value.saturating_sub(delta), -search::SCORE_INF);
                     failed_high_count = 0;
+                    failed_low = true;
                 } else if best_value >= beta {
+                    data.send_info(best_value, EntryType::Cut);
                     beta = min(best_value.saturating_add(delta), search::SCORE_INF);
                     failed_high_count += 1;
                 } else {
@@ -292,21 +640,40 @@ impl _WorkerThread {
 
                 delta = delta.saturating_add((delta / 4) + 5);
             }
-            if !THREADS.stopped() {
+            if !data.ctx.stopped() {
                 data.completed_depth = data.root_depth;
-                data.best_move = Some(data.moves[0]);
+                data.best_move = Some(EvalMove::new(data.moves[0].mv, data.moves[0].eval));
+                let iteration = Iteration {
+                    best_move: data.moves[0].mv,
+                    eval: data.moves[0].eval,
+                    failed_low,
+                };
+                data.history.push(iteration);
+                if data.limits.skill.enabled() {
+                    data.completed_moves = data.moves.clone();
+                }
                 if self.is_main {
                     let bm = data.moves[0].mv;
                     info!("sending best move so far({}) to engine controller...", bm);
-                    self.tx
-                        .send(make_info_message(data.moves[0], data.completed_depth));
+                    let eval = data.moves[0].eval;
+                    data.send_info(eval, EntryType::Pv);
+
+                    if data.limits_reached() || data.out_of_time() {
+                        if data.limits.infinite {
+                            info!("Done deepening, waiting for stop...");
+                            break;
+                        }
+                        info!("Stopping search...");
+                        THREADS.stop();
+                    }
                 }
             } else {
-                data.moves.sort_by_key(|&em| Reverse(em));
+                data.sort_moves();
             }
 
             data.root_depth += 1;
             depth = data.root_depth;
+            failed_low = false;
         }
 
         if !self.is_main {
@@ -314,7 +681,14 @@ impl _WorkerThread {
         }
 
         info!("waiting for search to be stopped...");
-        while !THREADS.stopped() {}
+        while !THREADS.stopped() {
+            /* keep the GUI up to date while the helpers go on searching */
+            if !data.moves.is_empty() && THREADS.elapsed() >= data.last_report + INFO_INTERVAL {
+                let eval = data.moves[0].eval;
+                data.send_info(eval, EntryType::Pv);
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
 
         THREADS.stop();
         info!("waiting for slave threads to go idle...");
@@ -323,44 +697,162 @@ impl _WorkerThread {
     }
 }
 
-fn make_info_message(best_move: EvalMove, depth: u8) -> UciMessage {
+/// Logs `s`, passing it on to the GUI through the engine controller while debug mode is on.
+fn debug_report(tx: &SyncSender<EngineMessage>, s: String) {
+    if debug_enabled() {
+        let _ = tx.send(UciMessage::info_string(s).into());
+    } else {
+        info!("{}", s);
+    }
+}
+
+/* Only report the move currently searched at the root once the search has gone on for a while. */
+const CURRMOVE_DELAY: Duration = Duration::from_secs(1);
+/* The longest the GUI goes without an update on the search. */
+const INFO_INTERVAL: Duration = Duration::from_secs(1);
+
+struct SearchStats {
+    depth: u8,
+    seldepth: u8,
+    nodes: u64,
+    time: Duration,
+    hashfull: u16,
+    tb_hits: u64,
+}
+
+fn search_stats(depth: u8, ctx: &SearchContext) -> SearchStats {
+    SearchStats {
+        depth,
+        seldepth: ctx.seldepth(),
+        nodes: ctx.nodes_searched(),
+        time: THREADS.elapsed(),
+        hashfull: TT.hashfull(),
+        tb_hits: THREADS.tb_hits(),
+    }
+}
+
+fn nps(stats: &SearchStats) -> u64 {
+    let millis = max(stats.time.as_millis(), 1);
+    (stats.nodes as u128 * 1000 / millis) as u64
+}
+
+fn time_attribute(time: Duration) -> UciInfoAttribute {
+    let millis = time.as_millis().try_into().unwrap_or(i64::MAX);
+    UciInfoAttribute::Time(vampirc_uci::Duration::milliseconds(millis))
+}
+
+/* Search progress without a line, for when there's no new one to report. */
+fn make_stats_message(stats: &SearchStats) -> UciMessage {
     use UciInfoAttribute::*;
-    use UciMessage::*;
 
-    Info(vec![
-        Pv(vec![best_move.mv]), //TODO: keep track of Principal Variation.
-        Depth(depth),
-        //TODO: report mating scores correctly
-        UciInfoAttribute::from_centipawns(best_move.eval as i32),
+    UciMessage::Info(vec![
+        Depth(stats.depth),
+        SelDepth(stats.seldepth),
+        Nodes(stats.nodes),
+        Nps(nps(stats)),
+        HashFull(stats.hashfull),
+        TbHits(stats.tb_hits),
+        time_attribute(stats.time),
     ])
 }
 
+fn make_info_message(
+    best_move: EvalMove,
+    pv: &[ChessMove],
+    bound: EntryType,
+    wdl: Option<Wdl>,
+    stats: &SearchStats,
+    multipv: Option<u16>,
+) -> UciMessage {
+    use UciInfoAttribute::*;
+    use UciMessage::*;
+
+    /* the PV may be from an unfinished iteration if the search was stopped */
+    let pv = if pv.first() == Some(&best_move.mv) {
+        pv.to_vec()
+    } else {
+        vec![best_move.mv]
+    };
+
+    let mut attrs = vec![Depth(stats.depth), SelDepth(stats.seldepth)];
+    attrs.extend(multipv.map(MultiPv));
+    attrs.push(score_attribute(best_move.eval, bound));
+    attrs.extend(wdl.map(|wdl| Any("wdl".to_string(), wdl.to_string())));
+    attrs.extend(vec![
+        Nodes(stats.nodes),
+        Nps(nps(stats)),
+        HashFull(stats.hashfull),
+        TbHits(stats.tb_hits),
+        time_attribute(stats.time),
+        Pv(pv),
+    ]);
+
+    Info(attrs)
+}
+
+fn score_attribute(eval: Eval, bound: EntryType) -> UciInfoAttribute {
+    use UciInfoAttribute::Score;
+
+    let mate = search::moves_to_mate(eval);
+
+    Score {
+        cp: if mate.is_none() {
+            Some(eval as i32)
+        } else {
+            None
+        },
+        mate,
+        lower_bound: if bound == EntryType::Cut {
+            Some(true)
+        } else {
+            None
+        },
+        upper_bound: if bound == EntryType::All {
+            Some(true)
+        } else {
+            None
+        },
+    }
+}
+
 impl Worker {
     pub fn start_search(&self) {
-        let data = &self.data;
-        let lock = data.lock.lock().unwrap();
-        data.searching.store(true, Ordering::Release);
-        data.cv.notify_one();
+        self.data.start_search();
+    }
+
+    /// Runs `job` on the worker thread instead of a search. Use wait() to wait for it to finish.
+    pub fn run(&self, job: Job) {
+        *self.data.job.lock().unwrap() = Some(job);
+        self.start_search();
     }
 
     pub fn wait(&self) {
-        let data = &self.data;
-        let lock = data.lock.lock().unwrap();
-        data.cv
-            .wait_while(lock, |_| data.searching.load(Ordering::Acquire));
+        self.data.wait();
     }
 
-    pub fn clear(&mut self) {}
+    /// Resets the worker's search state between games.
+    pub fn clear(&self) {
+        let mut data = self.data.root_data.lock().unwrap();
+        data.clear();
+        data.ctx.clear();
+    }
 
-    pub fn populate(&self, board: &Board) {
+    pub fn populate(&self, position: &Position, limits: &SearchLimits, nodes: &NodeCounters) {
         let mut lock = self.data.root_data.lock().unwrap();
-        lock.populate(board);
+        lock.populate(position, limits, nodes);
     }
 
     pub fn die(self) {
-        self.data.exit.store(true, Ordering::Release);
-        self.data.cv.notify_one();
-        self.handle.join();
+        {
+            let _lock = self.data.lock.lock().unwrap();
+            self.data.exit.store(true, Ordering::Release);
+            self.data.cv.notify_one();
+        }
+        let _ = self.handle.join();
+    }
+
+    pub fn nodes(&self) -> u64 {
+        self.data.nodes.load(Ordering::Relaxed)
     }
 
     pub fn vote(&self) -> Option<(ChessMove, Eval, u8)> {
@@ -372,49 +864,128 @@ impl Worker {
 }
 
 pub struct ThreadPool {
-    workers: RefCell<Vec<Worker>>,
-    nworkers: Cell<usize>,
+    workers: RwLock<Vec<Worker>>,
+    nworkers: AtomicUsize,
     stop: AtomicBool,
+    ponder: AtomicBool,
+    tb_hits: AtomicU64,
+    start_time: Mutex<Option<Instant>>,
+    /* microseconds into the search when the time limits started applying, set on ponderhit */
+    timed_from: AtomicU64,
 }
 
-unsafe impl Sync for ThreadPool {}
-
 impl ThreadPool {
-    pub const fn new() -> Self {
+    pub(crate) const fn new() -> Self {
         let stop = AtomicBool::new(false);
-        let mut workers = RefCell::new(Vec::new());
+        let workers = RwLock::new(Vec::new());
 
         ThreadPool {
             workers,
-            nworkers: Cell::new(0),
+            nworkers: AtomicUsize::new(0),
             stop,
+            ponder: AtomicBool::new(false),
+            tb_hits: AtomicU64::new(0),
+            start_time: Mutex::new(None),
+            timed_from: AtomicU64::new(0),
         }
     }
 
-    pub fn init(&self, tx: SyncSender<UciMessage>) {
-        let nworkers = num_cpus::get();
-        //let nworkers = 1;
+    pub fn init(&self, tx: SyncSender<EngineMessage>) {
+        let nworkers = options::THREADS.default as usize;
 
         assert!(nworkers > 0);
-        self.nworkers.set(nworkers);
-        let mut workers = self.workers.borrow_mut();
+        self.nworkers.store(nworkers, Ordering::Release);
+        let mut workers = self.workers.write().unwrap();
 
         for i in 0..nworkers {
             workers.push(Worker::new(i == 0, tx.clone()));
         }
     }
 
-    pub fn start_thinking(&self, board: &Board) {
+    /// Tears down all helper threads and spawns `nworkers - 1` new ones.
+    /// The main thread is kept as it is.
+    pub fn resize(&self, nworkers: usize) {
+        assert!(nworkers > 0);
+        self.wait_for_search_finished();
+        self.wait();
+
+        let mut workers = self.workers.write().unwrap();
+        let tx = workers[0].data.tx.clone();
+
+        info!("Shutting down {} helper threads...", workers.len() - 1);
+        for worker in workers.drain(1..) {
+            worker.die();
+        }
+
+        info!("Spawning {} helper threads...", nworkers - 1);
+        for _ in 1..nworkers {
+            let worker = Worker::new(false, tx.clone());
+            /* make sure it's parked in its idle loop before it can be woken up */
+            worker.wait();
+            workers.push(worker);
+        }
+
+        self.nworkers.store(nworkers, Ordering::Release);
+    }
+
+    pub fn start_thinking(&self, position: &Position, limits: &SearchLimits) {
         self.main().wait();
         self.stop.store(false, Ordering::Release);
+        self.ponder.store(limits.ponder, Ordering::Release);
+        *self.start_time.lock().unwrap() = Some(Instant::now());
+        self.timed_from.store(0, Ordering::Release);
+        self.tb_hits.store(0, Ordering::Relaxed);
+        TT.new_search();
+
+        /* in tablebase positions, only search the moves which keep the best outcome */
+        let mut limits = limits.clone();
+        let root_moves: Vec<ChessMove> = MoveGen::new_legal(&position.board)
+            .filter(|mv| limits.search_moves.is_empty() || limits.search_moves.contains(mv))
+            .collect();
+        if let Some(moves) = TB.filter_root_moves(position, &root_moves, limits.analysis) {
+            self.count_tb_hits(root_moves.len() as u64);
+            let kept = format!("tablebases kept {} of {}", moves.len(), root_moves.len());
+            debug_report(&self.main().tx, kept);
+            limits.search_moves = moves;
+        }
 
-        for worker in self.workers().iter() {
-            worker.populate(board);
+        let workers = self.workers();
+        let nodes: NodeCounters = workers.iter().map(|w| w.data.nodes.clone()).collect();
+        for worker in workers.iter() {
+            worker.populate(position, &limits, &nodes);
         }
+        drop(workers);
 
         self.main().start_search();
     }
 
+    /// Resets all search state between games, including the transposition table.
+    pub fn clear(&self) {
+        self.wait_for_search_finished();
+
+        for worker in self.workers().iter() {
+            worker.clear();
+        }
+        self.clear_tt();
+    }
+
+    /// Empties the transposition table, with each worker thread clearing a part of it.
+    pub fn clear_tt(&self) {
+        self.wait_for_search_finished();
+
+        let nworkers = self.nworkers();
+        for (idx, worker) in self.workers().iter().enumerate() {
+            worker.run(Box::new(move || TT.clear_part(idx, nworkers)));
+        }
+        for worker in self.workers().iter() {
+            worker.wait();
+        }
+    }
+
+    pub fn wait_for_search_finished(&self) {
+        self.main().wait();
+    }
+
     pub fn start_search(&self) {
         for i in 1..self.nworkers() {
             self.workers()[i].start_search();
@@ -422,32 +993,88 @@ impl ThreadPool {
     }
 
     pub fn wait(&self) {
-        for i in 1..self.nworkers() {
-            self.workers()[i].wait();
+        let helpers: Vec<WorkerThread> =
+            self.workers()[1..].iter().map(|w| w.data.clone()).collect();
+        for helper in helpers {
+            helper.wait();
         }
     }
 
-    pub fn elect_best_move(&self) -> ChessMove {
+    /// Picks the best move among the workers' results, weighing them by score and depth.
+    /// Returns None if no worker completed an iteration.
+    pub fn elect_best_move(&self) -> Option<ChessMove> {
         let votes: Vec<_> = self.workers().iter().filter_map(Worker::vote).collect();
-        let min_score = votes.iter().map(|(_, score, _)| score).min().unwrap();
+        let min_score = *votes.iter().map(|(_, score, _)| score).min()?;
         let mut election: HashMap<ChessMove, i32> = HashMap::new();
 
         votes.iter().for_each(|&(mv, score, depth)| {
             info!("move: {}, score: {}, depth: {}", mv, score, depth);
-            let value: i32 = (score - min_score + 14) as i32 * depth as i32;
+            /* widen first, mate scores at both ends would overflow an Eval */
+            let value = (score as i32 - min_score as i32 + 14) * depth as i32;
             election
                 .entry(mv)
                 .and_modify(|v| *v += value)
                 .or_insert(value);
         });
 
-        info!("Election results:");
+        let mut results: Vec<_> = election.into_iter().collect();
+        results.sort_by_key(|&(_, v)| Reverse(v));
+
+        let summary: Vec<String> = results
+            .iter()
+            .map(|(mv, v)| format!("{} {}", mv, v))
+            .collect();
+        debug_report(
+            &self.main().tx,
+            format!(
+                "election {} after {} nodes",
+                summary.join(", "),
+                self.nodes_searched()
+            ),
+        );
+
+        results.first().map(|&(mv, _)| mv)
+    }
 
-        election.iter().for_each(|(mv, eval)| {
-            info!("{}: {}", mv, eval);
-        });
+    /// The total number of nodes searched by all workers in the current search.
+    /// The search sums up the counters it was handed instead of going through the pool.
+    pub fn nodes_searched(&self) -> u64 {
+        self.workers().iter().map(Worker::nodes).sum()
+    }
+
+    /// The number of successful tablebase probes in the current search.
+    pub fn tb_hits(&self) -> u64 {
+        self.tb_hits.load(Ordering::Relaxed)
+    }
+
+    pub fn count_tb_hits(&self, hits: u64) {
+        self.tb_hits.fetch_add(hits, Ordering::Relaxed);
+    }
 
-        election.drain().max_by_key(|&(_, v)| v).unwrap().0
+    /// Time since the current search was started.
+    pub fn elapsed(&self) -> Duration {
+        self.start_time
+            .lock()
+            .unwrap()
+            .map_or(Duration::default(), |start| start.elapsed())
+    }
+
+    /// Time the time limits have been applying for, which is from the ponderhit on
+    /// when the search started out pondering.
+    pub fn timed_elapsed(&self) -> Duration {
+        let timed_from = Duration::from_micros(self.timed_from.load(Ordering::Acquire));
+        self.elapsed().saturating_sub(timed_from)
+    }
+
+    /// The GUI played the move we were pondering on, so the search is now timed.
+    pub fn ponderhit(&self) {
+        self.timed_from
+            .store(self.elapsed().as_micros() as u64, Ordering::Release);
+        self.ponder.store(false, Ordering::Release);
+    }
+
+    pub fn pondering(&self) -> bool {
+        self.ponder.load(Ordering::Acquire)
     }
 
     pub fn stop(&self) {
@@ -456,8 +1083,8 @@ impl ThreadPool {
 
     pub fn quit(&self) {
         self.stop();
-        self.wait();
-        let mut workers = self.workers.borrow_mut();
+        self.wait_for_search_finished();
+        let mut workers = self.workers.write().unwrap();
         for worker in workers.drain(..) {
             worker.die();
         }
@@ -466,18 +1093,17 @@ impl ThreadPool {
         self.stop.load(Ordering::Relaxed)
     }
 
-    fn workers(&self) -> Ref<Vec<Worker>> {
-        self.workers.borrow()
+    fn workers(&self) -> RwLockReadGuard<'_, Vec<Worker>> {
+        self.workers.read().unwrap()
     }
 
-    fn nworkers(&self) -> usize {
-        self.nworkers.get()
+    pub fn nworkers(&self) -> usize {
+        self.nworkers.load(Ordering::Acquire)
     }
 
-    fn main(&self) -> Ref<Worker> {
-        let workers = self.workers();
-
-        Ref::map(workers, |w| unsafe { w.get_unchecked(0) })
+    /* a handle rather than a guard, so waiting on it doesn't hold the pool locked */
+    fn main(&self) -> WorkerThread {
+        self.workers()[0].data.clone()
     }
 }
 
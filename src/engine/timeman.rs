@@ -0,0 +1,132 @@
+use chess::Color;
+use std::cmp::{max, min};
+use std::convert::TryInto;
+use std::time::Duration;
+use vampirc_uci::UciTimeControl;
+
+/* How many moves ahead the time is budgeted for in sudden death games. */
+const MOVE_HORIZON: u32 = 30;
+/* The hard deadline can stretch the soft one by this much. */
+const MAX_STRETCH: u32 = 4;
+/* Never plan to use more than this share of the remaining time on a single move. */
+const MAX_SHARE: f64 = 0.8;
+/* Always think at least this long, so that there's a move to report. */
+const MIN_THINK_TIME: Duration = Duration::from_millis(10);
+
+/// The time allotted for one move.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimeLimits {
+    /// Don't start another iteration after this.
+    pub soft: Duration,
+    /// Abort the search after this, no matter what.
+    pub hard: Duration,
+}
+
+impl TimeLimits {
+    fn new(soft: Duration, hard: Duration) -> Self {
+        let hard = max(hard, MIN_THINK_TIME);
+
+        TimeLimits {
+            soft: min(max(soft, MIN_THINK_TIME), hard),
+            hard,
+        }
+    }
+}
+
+/// Budgets the time for the next move of `to_move`.
+/// `overhead` is the time lost to the GUI and network on every move.
+/// Returns None if the search isn't timed.
+pub fn allocate(
+    time_control: UciTimeControl,
+    to_move: Color,
+    overhead: Duration,
+) -> Option<TimeLimits> {
+    match time_control {
+        UciTimeControl::MoveTime(duration) => {
+            let time = to_std(duration).saturating_sub(overhead);
+            Some(TimeLimits::new(time, time))
+        }
+        UciTimeControl::TimeLeft {
+            white_time,
+            black_time,
+            white_increment,
+            black_increment,
+            moves_to_go,
+        } => {
+            let (time, increment) = match to_move {
+                Color::White => (white_time, white_increment),
+                Color::Black => (black_time, black_increment),
+            };
+
+            time.map(|time| {
+                budget(
+                    to_std(time),
+                    increment.map_or(Duration::default(), to_std),
+                    moves_to_go,
+                    overhead,
+                )
+            })
+        }
+        _ => None,
+    }
+}
+
+fn budget(
+    time: Duration,
+    increment: Duration,
+    moves_to_go: Option<u8>,
+    overhead: Duration,
+) -> TimeLimits {
+    let moves = moves_to_go.map_or(MOVE_HORIZON, |n| (n as u32).clamp(1, MOVE_HORIZON));
+
+    /* the time we can count on until the horizon, paying the overhead on every move */
+    let available = (time + increment * (moves - 1)).saturating_sub(overhead * moves);
+    let usable = time.saturating_sub(overhead);
+
+    let hard = if moves == 1 {
+        usable
+    } else {
+        min(usable.mul_f64(MAX_SHARE), (available / moves) * MAX_STRETCH)
+    };
+
+    TimeLimits::new(available / moves, hard)
+}
+
+/* vampirc-uci durations can be negative if the GUI lets the clock run out. */
+fn to_std(duration: vampirc_uci::Duration) -> Duration {
+    duration.to_std().unwrap_or_default()
+}
+
+/// Converts a duration to the type vampirc-uci uses for times.
+pub fn to_uci(time: Duration) -> vampirc_uci::Duration {
+    vampirc_uci::Duration::milliseconds(time.as_millis().try_into().unwrap_or(i64::MAX))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn test_increment() {
+        let without = budget(ms(60_000), ms(0), None, ms(10));
+        let with = budget(ms(60_000), ms(1_000), None, ms(10));
+
+        assert!(with.soft > without.soft + ms(900));
+        assert!(with.soft <= with.hard);
+        assert!(with.hard < ms(60_000));
+    }
+
+    #[test]
+    fn test_low_time() {
+        let limits = budget(ms(50), ms(0), None, ms(100));
+        assert_eq!(limits.soft, MIN_THINK_TIME);
+        assert_eq!(limits.hard, MIN_THINK_TIME);
+
+        let limits = budget(ms(1_000), ms(0), Some(1), ms(100));
+        assert_eq!(limits.hard, ms(900));
+    }
+}
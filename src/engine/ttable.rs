@@ -1,3 +1,4 @@
+use std::cell::UnsafeCell;
 use std::cmp;
 use std::mem;
 use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
@@ -10,8 +11,13 @@ use crate::engine::eval::Eval;
 use lazy_static::lazy_static;
 use std::iter;
 
+pub const DEFAULT_TT_SIZE_MB: u64 = 1024;
+
 lazy_static! {
-    pub static ref TT: TTable = TTable::new(1024);
+    /* Empty until the engine allocates it at the size of the Hash option,
+     * so that a smaller size never has to allocate the default one first.
+     */
+    pub static ref TT: TTable = TTable::new(0);
 }
 
 pub type CacheTable = Arc<TTable>;
@@ -111,7 +117,7 @@ impl TTEntry {
         }
     }
     pub fn entry_type(&self) -> EntryType {
-        EntryType::from(self.genbound & (!0 << 3))
+        EntryType::from(self.genbound & (GEN_DELTA - 1))
     }
 }
 const CLUSTER_SIZE: usize = 4;
@@ -133,6 +139,12 @@ impl TTCluster {
     {
         self.entries[idx].store(new_entry.into(), Ordering::SeqCst);
     }
+
+    fn clear(&self) {
+        for entry in self.entries.iter() {
+            entry.store(0, Ordering::Relaxed);
+        }
+    }
 }
 
 const GEN_BITS: u8 = 3;
@@ -141,22 +153,57 @@ const GEN_CYCLE: u16 = 0xFF + (GEN_DELTA as u16);
 const GEN_MASK: u16 = (0xFF << GEN_BITS) & 0xFF;
 
 pub struct TTable {
-    cluster_count: u64,
+    cluster_count: AtomicU64,
     gen8: AtomicU8,
-    table: Vec<TTCluster>,
+    table: UnsafeCell<Vec<TTCluster>>,
 }
 
+/* The table itself is only ever mutated through resize(),
+ * which must not be called while a search is in progress.
+ */
+unsafe impl Sync for TTable {}
+
 impl TTable {
     pub fn new(mb_size: u64) -> Self {
+        let table = TTable {
+            cluster_count: AtomicU64::new(0),
+            gen8: AtomicU8::default(),
+            table: UnsafeCell::new(Vec::new()),
+        };
+        table.resize(mb_size);
+
+        table
+    }
+
+    /// Reallocates the table to hold `mb_size` megabytes, discarding its contents.
+    ///
+    /// The caller must make sure no search is running while the table is resized.
+    pub fn resize(&self, mb_size: u64) {
         let cluster_count = (mb_size * (1 << 20)) / mem::size_of::<TTCluster>() as u64;
-        let gen8 = AtomicU8::default();
-        let mut table = Vec::new();
+        let table = unsafe { &mut *self.table.get() };
+
+        /* free the old table before allocating the new one */
+        *table = Vec::new();
         table.extend(iter::repeat_with(TTCluster::default).take(cluster_count as usize));
 
-        TTable {
-            cluster_count,
-            gen8,
-            table,
+        self.cluster_count.store(cluster_count, Ordering::Relaxed);
+        self.gen8.store(0, Ordering::Relaxed);
+    }
+
+    /// Empties part `idx` of `count` equal parts of the table, so that the worker
+    /// threads can share the work of clearing it.
+    ///
+    /// Like resize(), this must not be called while a search is running.
+    pub fn clear_part(&self, idx: usize, count: usize) {
+        let table = self.table();
+        let chunk_size = table.len().div_ceil(cmp::max(count, 1));
+        let start = cmp::min(idx * chunk_size, table.len());
+        let end = cmp::min(start + chunk_size, table.len());
+
+        table[start..end].iter().for_each(TTCluster::clear);
+
+        if idx == 0 {
+            self.gen8.store(0, Ordering::Relaxed);
         }
     }
 
@@ -171,7 +218,7 @@ impl TTable {
 
         for idx in 0..CLUSTER_SIZE {
             let mut entry: TTEntry = cluster.get_entry(idx);
-            if (entry.key16 == key16) || entry.depth != 0 {
+            if (entry.key16 == key16) || entry.depth == 0 {
                 entry.genbound = self.gen8() | (entry.genbound & (GEN_DELTA - 1));
                 cluster.save_entry(idx, entry);
 
@@ -218,12 +265,42 @@ impl TTable {
         self.gen8.fetch_add(GEN_DELTA, Ordering::Relaxed);
     }
 
+    /// The size the table was last allocated with.
+    pub fn size_mb(&self) -> u64 {
+        self.table().len() as u64 * mem::size_of::<TTCluster>() as u64 / (1 << 20)
+    }
+
+    /// The number of entries the table can hold.
+    pub fn capacity(&self) -> usize {
+        self.table().len() * CLUSTER_SIZE
+    }
+
+    /// The permille of sampled entries written during the current search.
+    pub fn hashfull(&self) -> u16 {
+        let sample = cmp::min(1000 / CLUSTER_SIZE, self.table().len());
+        if sample == 0 {
+            return 0;
+        }
+
+        let used = self.table()[..sample]
+            .iter()
+            .flat_map(|cluster| (0..CLUSTER_SIZE).map(move |idx| cluster.get_entry(idx)))
+            .filter(|entry| entry.depth != 0 && entry.genbound & GEN_MASK as u8 == self.gen8())
+            .count();
+
+        (used * 1000 / (sample * CLUSTER_SIZE)) as u16
+    }
+
     fn gen8(&self) -> u8 {
         self.gen8.load(Ordering::Relaxed)
     }
 
+    fn table(&self) -> &Vec<TTCluster> {
+        unsafe { &*self.table.get() }
+    }
+
     fn get_cluster(&self, idx: usize) -> &TTCluster {
-        unsafe { self.table.get_unchecked(idx) }
+        unsafe { self.table().get_unchecked(idx) }
     }
 
     fn get_entry(&self, handle: TTHandle) -> TTEntry {
@@ -248,8 +325,8 @@ impl TTable {
     }
 
     fn get_cluster_idx(&self, hash: u64) -> usize {
-        let idx: usize = mul_hi_64(hash, self.cluster_count);
-        debug_assert!(idx < self.table.len());
+        let idx: usize = mul_hi_64(hash, self.cluster_count.load(Ordering::Relaxed));
+        debug_assert!(idx < self.table().len());
         idx
     }
 }
@@ -304,6 +381,18 @@ pub enum EntryType {
     All = 1 << 0,
 }
 
+impl EntryType {
+    /// Whether a score stored with this bound settles a node searched with the window
+    /// `alpha..beta`. Only exact scores always do; the others are just bounds.
+    pub fn cuts_off(self, eval: Eval, alpha: Eval, beta: Eval) -> bool {
+        match self {
+            EntryType::Pv => true,
+            EntryType::Cut => eval >= beta,
+            EntryType::All => eval <= alpha,
+        }
+    }
+}
+
 impl From<u8> for EntryType {
     fn from(uint: u8) -> Self {
         unsafe { mem::transmute::<u8, EntryType>(uint) }
@@ -327,6 +416,67 @@ mod test {
         assert_eq!(mem::size_of::<AtomicU64>(), 8);
     }
 
+    #[test]
+    fn test_resize() {
+        let tt = TTable::new(1);
+        assert_eq!(tt.table().len(), (1 << 20) / 32);
+
+        let board = Board::default();
+        let mv = ChessMove::new(Square::E2, Square::E4, None);
+        let (_, handle) = tt.probe(&board);
+        tt.save(handle, &board, mv, 10, 5, EntryType::Pv);
+        assert!(tt.probe(&board).0.is_some());
+
+        tt.resize(2);
+        assert_eq!(tt.table().len(), (2 << 20) / 32);
+        assert_eq!(tt.size_mb(), 2);
+        assert!(tt.probe(&board).0.is_none());
+    }
+
+    #[test]
+    fn test_empty() {
+        let tt = TTable::new(0);
+        assert_eq!(tt.size_mb(), 0);
+        assert_eq!(tt.capacity(), 0);
+        assert_eq!(tt.hashfull(), 0);
+        tt.clear_part(0, 1);
+    }
+
+    #[test]
+    fn test_clear() {
+        let tt = TTable::new(1);
+        let mv = ChessMove::new(Square::E2, Square::E4, None);
+        let boards = [Board::default(), Board::default().make_move_new(mv)];
+
+        for board in boards.iter() {
+            let (_, handle) = tt.probe(board);
+            tt.save(handle, board, mv, 10, 5, EntryType::Pv);
+        }
+        tt.new_search();
+
+        for idx in 0..3 {
+            tt.clear_part(idx, 3);
+        }
+        assert!(boards.iter().all(|board| tt.probe(board).0.is_none()));
+        assert_eq!(tt.gen8(), 0);
+    }
+
+    #[test]
+    fn test_hashfull() {
+        let tt = TTable::new(1);
+        let mv = ChessMove::new(Square::E2, Square::E4, None);
+        assert_eq!(tt.hashfull(), 0);
+
+        for idx in 0..CLUSTER_SIZE {
+            let entry = TTEntry::new(0, mv.into(), 0, 1, tt.gen8() | u8::from(EntryType::Pv));
+            tt.save_entry((0, idx), entry);
+        }
+        assert_eq!(tt.hashfull(), 4);
+
+        tt.new_search();
+        assert_eq!(tt.hashfull(), 0);
+    }
+
     #[test]
     fn test_move_conversion() {
         for src in 0..64 {
@@ -358,4 +508,49 @@ mod test {
             false
         }
     }
+
+    #[test]
+    fn test_entry_type() {
+        let tt = TTable::new(1);
+        let mv = ChessMove::new(Square::E2, Square::E4, None);
+        let boards = [
+            Board::default(),
+            Board::default().make_move_new(mv),
+            Board::default().null_move().unwrap(),
+        ];
+        let entry_types = [EntryType::Pv, EntryType::Cut, EntryType::All];
+        tt.new_search();
+
+        for (board, &et) in boards.iter().zip(entry_types.iter()) {
+            let (_, handle) = tt.probe(&board);
+            tt.save(handle, board, mv, 10, 5, et);
+            assert!(tt.probe(board).0.unwrap().entry_type() == et);
+        }
+    }
+
+    #[test]
+    fn test_probe_other_position() {
+        let tt = TTable::new(1);
+        let mv = ChessMove::new(Square::E2, Square::E4, None);
+        let board = Board::default();
+
+        /* an entry of another position in the slot the board would use */
+        let (_, handle) = tt.probe(&board);
+        let key16 = !(board.get_hash() as Key16);
+        let pv = u8::from(EntryType::Pv);
+        tt.save_entry(handle, TTEntry::new(key16, mv.into(), 10, 5, pv));
+
+        let (entry, other_handle) = tt.probe(&board);
+        assert!(entry.is_none());
+        assert!(other_handle != handle);
+    }
+
+    #[test]
+    fn test_cuts_off() {
+        assert!(EntryType::Pv.cuts_off(0, 10, 20));
+        assert!(EntryType::Cut.cuts_off(20, 10, 20));
+        assert!(!EntryType::Cut.cuts_off(15, 10, 20));
+        assert!(EntryType::All.cuts_off(10, 10, 20));
+        assert!(!EntryType::All.cuts_off(15, 10, 20));
+    }
 }
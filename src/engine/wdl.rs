@@ -0,0 +1,279 @@
+use chess::{Board, Color, Piece};
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::engine::eval::Eval;
+use crate::engine::search;
+
+/* Material counted the traditional way, so that a full board is 78. */
+const PIECE_MATERIAL: [u32; 6] = [1, 3, 3, 5, 9, 0];
+const MAX_MATERIAL: u32 = 78;
+
+/// Parameters fitted with `tune fitwdl` on the 121703 quiet positions of 2000
+/// `tune selfplay` games at 50 ms per move (928 white wins, 187 draws, 885 losses).
+/// Refit them whenever the evaluation changes.
+///
+/// Fast self-play rarely draws with much material left, so an even position on a full
+/// board comes out at about 47/7/47 and a 125 cp edge is already an even bet to win.
+/// As material comes off the curve flattens: at half material an even position is
+/// drawn about a quarter of the time, and with no material left about 60% of the time,
+/// with winning only more likely than not from about 470 cp.
+pub const MODEL: WdlModel = WdlModel {
+    a: [472.078125, -347.25],
+    b: [338.84375, 582.0625],
+};
+
+/// Expected win/draw/loss rates in per mille, from the side to move's point of view.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Wdl {
+    pub win: u16,
+    pub draw: u16,
+    pub loss: u16,
+}
+
+impl fmt::Display for Wdl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} {}", self.win, self.draw, self.loss)
+    }
+}
+
+/// Maps evaluations to win rates with a logistic curve, `1 / (1 + e^((a - eval) / b))`.
+///
+/// `a` is the evaluation where a win is as likely as not, and `b` how spread out the
+/// curve is. Both depend linearly on the material left on the board, since the same
+/// advantage is easier to convert with fewer pieces around.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WdlModel {
+    pub a: [f64; 2],
+    pub b: [f64; 2],
+}
+
+/// The material on the board, from 0 to 78.
+pub fn material(board: &Board) -> u32 {
+    let pieces = [
+        Piece::Pawn,
+        Piece::Knight,
+        Piece::Bishop,
+        Piece::Rook,
+        Piece::Queen,
+    ];
+
+    pieces
+        .iter()
+        .map(|&piece| board.pieces(piece).popcnt() * PIECE_MATERIAL[piece.to_index()])
+        .sum()
+}
+
+impl WdlModel {
+    fn params(&self, material: u32) -> (f64, f64) {
+        let x = material.min(MAX_MATERIAL) as f64 / MAX_MATERIAL as f64;
+        let a = self.a[0] + self.a[1] * x;
+        let b = self.b[0] + self.b[1] * x;
+
+        (a.max(0.0), b.max(1.0))
+    }
+
+    /// The probability of winning with this evaluation.
+    pub fn win_rate(&self, eval: Eval, material: u32) -> f64 {
+        let (a, b) = self.params(material);
+
+        1.0 / (1.0 + ((a - eval as f64) / b).exp())
+    }
+
+    /// The probabilities of a win, a draw and a loss.
+    fn probabilities(&self, eval: Eval, material: u32) -> [f64; 3] {
+        let win = self.win_rate(eval, material);
+        let loss = self.win_rate(-eval, material);
+
+        [win, (1.0 - win - loss).max(0.0), loss]
+    }
+
+    pub fn wdl(&self, eval: Eval, board: &Board) -> Wdl {
+        match search::moves_to_mate(eval) {
+            Some(n) if n > 0 => Wdl {
+                win: 1000,
+                draw: 0,
+                loss: 0,
+            },
+            Some(_) => Wdl {
+                win: 0,
+                draw: 0,
+                loss: 1000,
+            },
+            None => {
+                let [win, _, loss] = self.probabilities(eval, material(board));
+                let win = (win * 1000.0).round() as u16;
+                let loss = (loss * 1000.0).round() as u16;
+
+                Wdl {
+                    win,
+                    draw: 1000u16.saturating_sub(win + loss),
+                    loss,
+                }
+            }
+        }
+    }
+
+    /// Fits the model to game outcomes by maximizing their likelihood.
+    pub fn fit(samples: &[Sample]) -> WdlModel {
+        /* identical positions only need to be scored once */
+        let mut counts: HashMap<(Eval, u32), [f64; 3]> = HashMap::new();
+        for sample in samples {
+            let outcomes = counts.entry((sample.eval, sample.material)).or_default();
+            outcomes[sample.outcome as usize] += 1.0;
+        }
+
+        let log_likelihood = |model: &WdlModel| -> f64 {
+            counts
+                .iter()
+                .map(|(&(eval, material), outcomes)| {
+                    let p = model.probabilities(eval, material);
+                    (0..3)
+                        .map(|i| outcomes[i] * p[i].max(1e-12).ln())
+                        .sum::<f64>()
+                })
+                .sum()
+        };
+
+        /* Pattern search. Try nudging each parameter both ways,
+         * and narrow the steps once nothing improves anymore.
+         */
+        let mut model = MODEL;
+        let mut best = log_likelihood(&model);
+        let mut step = 16.0;
+
+        while step > 0.01 {
+            let mut improved = false;
+
+            for param in 0..4 {
+                for &dir in [1.0, -1.0].iter() {
+                    let mut candidate = model;
+                    if param < 2 {
+                        candidate.a[param] += dir * step;
+                    } else {
+                        candidate.b[param - 2] += dir * step;
+                    }
+
+                    let value = log_likelihood(&candidate);
+                    if value > best {
+                        model = candidate;
+                        best = value;
+                        improved = true;
+                    }
+                }
+            }
+
+            if !improved {
+                step /= 2.0;
+            }
+        }
+
+        model
+    }
+}
+
+/// How a game ended for the side to move in a sampled position.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Win = 0,
+    Draw = 1,
+    Loss = 2,
+}
+
+/// A position from a self-play game, with the engine's evaluation of it and the game result.
+#[derive(Clone, Copy, Debug)]
+pub struct Sample {
+    pub eval: Eval,
+    pub material: u32,
+    pub outcome: Outcome,
+}
+
+impl FromStr for Sample {
+    type Err = String;
+
+    /// Parses a line of the form `<fen> | <eval> | <result>`, where the evaluation is in
+    /// centipawns from the side to move's point of view, and the result is `1-0`, `0-1`
+    /// or `1/2-1/2`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<&str> = s.split('|').map(str::trim).collect();
+        let (fen, eval, result) = match fields.as_slice() {
+            [fen, eval, result] => (fen, eval, result),
+            _ => return Err(format!("expected <fen> | <eval> | <result>, got {}", s)),
+        };
+
+        let board = Board::from_str(fen).map_err(|e| format!("invalid fen {}: {}", fen, e))?;
+        let eval = eval
+            .parse::<Eval>()
+            .map_err(|e| format!("invalid eval {}: {}", eval, e))?;
+
+        let white_outcome = match *result {
+            "1-0" => Outcome::Win,
+            "0-1" => Outcome::Loss,
+            "1/2-1/2" => Outcome::Draw,
+            _ => return Err(format!("invalid result {}", result)),
+        };
+
+        let outcome = match (board.side_to_move(), white_outcome) {
+            (Color::Black, Outcome::Win) => Outcome::Loss,
+            (Color::Black, Outcome::Loss) => Outcome::Win,
+            (_, outcome) => outcome,
+        };
+
+        Ok(Sample {
+            eval,
+            material: material(&board),
+            outcome,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_fit() {
+        let truth = WdlModel {
+            a: [100.0, 150.0],
+            b: [50.0, 30.0],
+        };
+
+        /* outcomes in exactly the proportions the model predicts */
+        let mut samples = Vec::new();
+        for material in (10..=70).step_by(20) {
+            for eval in (-400..=400).step_by(50) {
+                let p = truth.probabilities(eval, material);
+                for (i, &outcome) in [Outcome::Win, Outcome::Draw, Outcome::Loss]
+                    .iter()
+                    .enumerate()
+                {
+                    let n = (p[i] * 200.0).round() as usize;
+                    samples.extend((0..n).map(|_| Sample {
+                        eval,
+                        material,
+                        outcome,
+                    }));
+                }
+            }
+        }
+
+        let fitted = WdlModel::fit(&samples);
+        for &material in [10, 40, 70].iter() {
+            for &eval in [-200, 0, 100, 300].iter() {
+                let diff = fitted.win_rate(eval, material) - truth.win_rate(eval, material);
+                assert!(diff.abs() < 0.02, "{:?}", fitted);
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_sample() {
+        let sample: Sample = "4k3/8/8/8/8/8/8/R3K3 b - - 0 1 | -500 | 1-0"
+            .parse()
+            .unwrap();
+        assert_eq!(sample.eval, -500);
+        assert_eq!(sample.material, 5);
+        assert_eq!(sample.outcome, Outcome::Loss);
+    }
+}
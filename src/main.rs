@@ -6,15 +6,15 @@ use fibers::io::stdin;
 use std::io::{BufRead, BufReader};
 use std::path::Path;
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, SyncSender};
 use std::sync::Arc;
 use std::thread;
 use std::time::Duration;
 use std::{io, panic};
-use vampirc_uci::{parse_one, UciMessage};
+use vampirc_uci::{parse_one, UciMessage, UciTimeControl};
 
-use engine::Engine;
-
-mod engine;
+use chessengine::engine::{self, Engine, EngineMessage};
+use chessengine::xboard::{Event, XBoard};
 
 enum EngineError {
     IOError(io::Error),
@@ -34,7 +34,19 @@ impl From<SetLoggerError> for EngineError {
 
 fn main() {
     init_logger();
-    uci_loop();
+
+    let mut stdin = BufReader::new(stdin());
+    let first_command = read_first_command(&mut stdin);
+    let result = if first_command.trim() == "xboard" {
+        xboard_loop(stdin)
+    } else {
+        uci_loop(stdin, first_command)
+    };
+
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
 }
 fn init_logger() -> Result<(), EngineError> {
     let path = Path::new("engine.log");
@@ -66,14 +78,25 @@ fn init_logger() -> Result<(), EngineError> {
     Ok(())
 }
 
-fn uci_loop() -> io::Result<()> {
+/* The first command tells which protocol the GUI speaks. */
+fn read_first_command(stdin: &mut impl BufRead) -> String {
+    let mut input = String::new();
+    loop {
+        match stdin.read_line(&mut input) {
+            Err(_) => thread::sleep(Duration::from_millis(2)),
+            Ok(n) if n > 0 && input.trim().is_empty() => input.clear(),
+            Ok(_) => return input,
+        }
+    }
+}
+
+fn uci_loop(mut stdin: impl BufRead, first_command: String) -> io::Result<()> {
     let mut input = String::new();
     let running = Arc::new(AtomicBool::new(true));
     let (handle, tx) = {
         let engine = Engine::default();
         engine.start()
     };
-    let mut stdin = BufReader::new(stdin());
 
     {
         let tx = tx.clone();
@@ -81,22 +104,21 @@ fn uci_loop() -> io::Result<()> {
 
         ctrlc::set_handler(move || {
             info!("received SIGINT/SIGTERM. Quitting...");
-            tx.send(UciMessage::Quit);
+            tx.send(UciMessage::Quit.into());
             running.store(false, Ordering::Relaxed);
             info!("Told main thread to quit.");
         });
     }
 
+    if !forward_command(&first_command, &tx, &running) {
+        running.store(false, Ordering::Release);
+    }
+
     while running.load(Ordering::Acquire) {
         if stdin.read_line(&mut input).is_err() {
             thread::sleep(Duration::from_millis(2));
         } else {
-            if input.starts_with("quit") {
-                running.store(false, Ordering::Release);
-            }
-            let message = parse_one(&input);
-
-            if tx.send(message).is_err() {
+            if !forward_command(&input, &tx, &running) {
                 break;
             }
 
@@ -108,3 +130,91 @@ fn uci_loop() -> io::Result<()> {
     handle.join();
     Ok(())
 }
+
+/* Returns false if the engine is gone. */
+fn forward_command(input: &str, tx: &SyncSender<EngineMessage>, running: &AtomicBool) -> bool {
+    if input.starts_with("quit") {
+        running.store(false, Ordering::Release);
+    }
+
+    tx.send(to_engine_message(input, parse_one(input))).is_ok()
+}
+
+/// Speaks CECP to an XBoard GUI, translating to and from UCI for the engine.
+fn xboard_loop(mut stdin: impl BufRead + Send + 'static) -> io::Result<()> {
+    let (events_tx, events_rx) = mpsc::channel();
+
+    {
+        let events_tx = events_tx.clone();
+        engine::redirect_output(move |message| {
+            let _ = events_tx.send(Event::Engine(message));
+        });
+    }
+
+    let (handle, tx) = Engine::default().start();
+
+    {
+        let events_tx = events_tx.clone();
+        let _ = ctrlc::set_handler(move || {
+            info!("received SIGINT/SIGTERM. Quitting...");
+            let _ = events_tx.send(Event::Input("quit".to_string()));
+        });
+    }
+
+    /* read stdin on a thread of its own, so that engine replies get through in the meantime */
+    thread::spawn(move || {
+        let mut input = String::new();
+        loop {
+            match stdin.read_line(&mut input) {
+                Err(_) => thread::sleep(Duration::from_millis(2)),
+                Ok(0) => {
+                    let _ = events_tx.send(Event::Input("quit".to_string()));
+                    return;
+                }
+                Ok(_) => {
+                    if events_tx.send(Event::Input(input.clone())).is_err() {
+                        return;
+                    }
+                    input.clear();
+                }
+            }
+        }
+    });
+
+    let mut xboard = XBoard::new(tx);
+    for event in events_rx {
+        match event {
+            Event::Input(line) => {
+                if !xboard.handle_input(&line) {
+                    break;
+                }
+            }
+            Event::Engine(message) => xboard.handle_engine(message),
+        }
+    }
+
+    info!("Joining engine controller thread...");
+    let _ = handle.join();
+    Ok(())
+}
+
+/// vampirc-uci parses `go ponder wtime ...` as a regular timed `go`,
+/// so look for the ponder flag ourselves.
+fn to_engine_message(input: &str, message: UciMessage) -> EngineMessage {
+    match message {
+        UciMessage::Go {
+            time_control,
+            search_control,
+        } if is_go_ponder(input) => EngineMessage::GoPonder {
+            time_control: time_control.filter(|tc| *tc != UciTimeControl::Ponder),
+            search_control,
+        },
+        message => message.into(),
+    }
+}
+
+fn is_go_ponder(input: &str) -> bool {
+    input
+        .split_whitespace()
+        .any(|token| token.eq_ignore_ascii_case("ponder"))
+}
@@ -0,0 +1,503 @@
+use chess::{ChessMove, Color};
+use log::info;
+use std::collections::VecDeque;
+use std::str::FromStr;
+use std::sync::mpsc::SyncSender;
+use std::time::Duration;
+use vampirc_uci::{UciFen, UciInfoAttribute, UciMessage, UciSearchControl, UciTimeControl};
+
+use crate::engine::options;
+use crate::engine::position::{self, GameEnd, Position};
+use crate::engine::timeman::to_uci;
+use crate::engine::{EngineMessage, NAME};
+
+/* Thinking output shows a mate in n moves as 100000 + n. */
+const MATE_SCORE: i32 = 100_000;
+
+/* xboard's own default, 40 moves in 5 minutes. */
+const DEFAULT_LEVEL: Level = Level {
+    moves: 40,
+    base: Duration::from_secs(300),
+    increment: Duration::from_secs(0),
+};
+
+/// What the CECP front end reacts to: lines from the GUI, and replies from the engine.
+pub enum Event {
+    Input(String),
+    Engine(UciMessage),
+}
+
+/* What a `go` sent to the engine is for. The engine answers every one with exactly one bestmove,
+ * in order, so they're queued up until then.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Search {
+    /// Play the best move.
+    Play,
+    /// Show the analysis until told otherwise.
+    Analyze,
+    /// Cancelled, so the result is thrown away.
+    Discard,
+}
+
+/// A `level` time control: `moves` moves (0 for the whole game) in `base`, plus `increment`
+/// for every move.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Level {
+    moves: u32,
+    base: Duration,
+    increment: Duration,
+}
+
+/// Keeps the game for an XBoard GUI, and translates between CECP and the UCI messages the engine
+/// understands. UCI leaves the game to the GUI, but a CECP engine makes its own moves.
+pub struct XBoard {
+    tx: SyncSender<EngineMessage>,
+    fen: Option<String>,
+    moves: Vec<ChessMove>,
+    position: Position,
+    /* only think when told to with `go` */
+    force: bool,
+    analyzing: bool,
+    post: bool,
+    level: Level,
+    move_time: Option<Duration>,
+    depth: Option<u8>,
+    /* the clocks, from `time` and `otim` */
+    time: Option<Duration>,
+    opponent_time: Option<Duration>,
+    searches: VecDeque<Search>,
+}
+
+impl XBoard {
+    pub fn new(tx: SyncSender<EngineMessage>) -> Self {
+        XBoard {
+            tx,
+            fen: None,
+            moves: Vec::new(),
+            position: Position::default(),
+            force: false,
+            analyzing: false,
+            post: false,
+            level: DEFAULT_LEVEL,
+            move_time: None,
+            depth: None,
+            time: None,
+            opponent_time: None,
+            searches: VecDeque::new(),
+        }
+    }
+
+    /// Handles a command from the GUI. Returns false once it's time to quit.
+    pub fn handle_input(&mut self, line: &str) -> bool {
+        info!("rx: {}", line.trim_end());
+        let line = line.trim();
+        let (command, args) = line.split_once(' ').unwrap_or((line, ""));
+        let args = args.trim();
+
+        match command {
+            "protover" => send(format!(
+                "feature myname=\"{}\" ping=1 setboard=1 usermove=1 playother=1 analyze=1 \
+                 colors=0 san=0 time=1 sigint=0 sigterm=0 reuse=1 memory=1 smp=1 done=1",
+                NAME
+            )),
+            "new" => self.new_game(),
+            "force" => {
+                self.cancel();
+                self.force = true;
+            }
+            "go" => {
+                self.force = false;
+                self.think();
+            }
+            "playother" => self.force = false,
+            "usermove" => self.user_move(args),
+            "level" => match parse_level(args) {
+                Some(level) => {
+                    self.level = level;
+                    self.move_time = None;
+                }
+                None => send(format!("Error (invalid level): {}", args)),
+            },
+            "st" => match parse_seconds(args) {
+                Some(time) => self.move_time = Some(time),
+                None => send(format!("Error (invalid time): {}", args)),
+            },
+            "sd" => match args.parse::<u32>() {
+                Ok(depth) => self.depth = Some(depth.clamp(1, u8::MAX as u32) as u8),
+                Err(_) => send(format!("Error (invalid depth): {}", args)),
+            },
+            "time" => self.time = parse_centiseconds(args),
+            "otim" => self.opponent_time = parse_centiseconds(args),
+            "post" => self.post = true,
+            "nopost" => self.post = false,
+            "analyze" => {
+                self.analyzing = true;
+                self.force = true;
+                self.set_option(options::ANALYSE_MODE.name, "true");
+                self.analyze();
+            }
+            "exit" if self.analyzing => {
+                self.cancel();
+                self.analyzing = false;
+                self.set_option(options::ANALYSE_MODE.name, "false");
+            }
+            "undo" => self.undo(1),
+            "remove" => self.undo(2),
+            "result" => {
+                self.cancel();
+                self.force = true;
+            }
+            "setboard" => self.set_board(args),
+            "ping" => send(format!("pong {}", args)),
+            /* move now */
+            "?" => {
+                if self.searches.back() == Some(&Search::Play) {
+                    self.send_engine(UciMessage::Stop);
+                }
+            }
+            "memory" => self.set_option(options::HASH.name, args),
+            "cores" => self.set_option(options::THREADS.name, args),
+            "quit" => {
+                self.cancel();
+                self.send_engine(UciMessage::Quit);
+                return false;
+            }
+            "" | "xboard" | "accepted" | "rejected" | "random" | "computer" | "name" | "rating"
+            | "ics" | "hard" | "easy" | "." => {}
+            /* GUIs which don't know the usermove feature send bare moves */
+            _ if ChessMove::from_str(command).is_ok() => self.user_move(command),
+            _ => send(format!("Error (unknown command): {}", command)),
+        }
+
+        true
+    }
+
+    /// Handles a reply from the engine.
+    pub fn handle_engine(&mut self, message: UciMessage) {
+        match message {
+            UciMessage::BestMove { best_move, .. } => self.search_done(Some(best_move)),
+            UciMessage::Unknown(ref text, _) if text == "bestmove 0000" => self.search_done(None),
+            UciMessage::Info(attrs) => self.info(&attrs),
+            message => info!("Not passing {} on to the GUI", message),
+        }
+    }
+
+    fn new_game(&mut self) {
+        self.cancel();
+        self.send_engine(UciMessage::UciNewGame);
+
+        self.fen = None;
+        self.moves.clear();
+        self.replay();
+        self.force = false;
+        self.depth = None;
+        self.time = None;
+        self.opponent_time = None;
+
+        if self.analyzing {
+            self.analyze();
+        }
+    }
+
+    fn user_move(&mut self, text: &str) {
+        let mv = match ChessMove::from_str(text) {
+            Ok(mv) if self.position.board.legal(mv) => mv,
+            _ => {
+                send(format!("Illegal move: {}", text));
+                return;
+            }
+        };
+
+        self.cancel();
+        self.moves.push(mv);
+        self.replay();
+
+        if self.analyzing {
+            self.analyze();
+        } else if !self.force {
+            self.think();
+        }
+    }
+
+    fn undo(&mut self, plies: usize) {
+        self.cancel();
+        let plies = plies.min(self.moves.len());
+        self.moves.truncate(self.moves.len() - plies);
+        self.replay();
+
+        if self.analyzing {
+            self.analyze();
+        }
+    }
+
+    fn set_board(&mut self, fen: &str) {
+        if let Err(e) = position::parse_fen(fen) {
+            send(format!("tellusererror Illegal position: {}", e));
+            return;
+        }
+
+        self.cancel();
+        self.fen = Some(fen.to_string());
+        self.moves.clear();
+        self.replay();
+
+        if self.analyzing {
+            self.analyze();
+        }
+    }
+
+    /* Rebuilds the position from the game, whose moves have all been checked already. */
+    fn replay(&mut self) {
+        match position::setup(self.fen.as_deref(), &self.moves) {
+            Ok(position) => self.position = position,
+            Err(e) => info!("Could not replay the game: {}", e),
+        }
+    }
+
+    /// Starts searching for a move to play, unless the game is over.
+    fn think(&mut self) {
+        if let Some(result) = self.result() {
+            send(result.to_string());
+            return;
+        }
+
+        self.send_position();
+        self.searches.push_back(Search::Play);
+        self.send_engine(UciMessage::Go {
+            time_control: Some(self.time_control()),
+            search_control: self.depth.map(UciSearchControl::depth),
+        });
+    }
+
+    fn analyze(&mut self) {
+        self.cancel();
+        self.send_position();
+        self.searches.push_back(Search::Analyze);
+        self.send_engine(UciMessage::Go {
+            time_control: Some(UciTimeControl::Infinite),
+            search_control: None,
+        });
+    }
+
+    /* Stops the searches in flight, and throws away their results. */
+    fn cancel(&mut self) {
+        if self
+            .searches
+            .iter()
+            .any(|&search| search != Search::Discard)
+        {
+            self.send_engine(UciMessage::Stop);
+        }
+
+        self.searches
+            .iter_mut()
+            .for_each(|search| *search = Search::Discard);
+    }
+
+    fn search_done(&mut self, best_move: Option<ChessMove>) {
+        match (self.searches.pop_front(), best_move) {
+            (Some(Search::Play), Some(mv)) => {
+                self.moves.push(mv);
+                self.replay();
+                send(format!("move {}", mv));
+
+                if let Some(result) = self.result() {
+                    send(result.to_string());
+                }
+            }
+            (Some(_), _) => {}
+            (None, _) => info!("Search result with no search running, ignoring..."),
+        }
+    }
+
+    fn info(&self, attrs: &[UciInfoAttribute]) {
+        if let [UciInfoAttribute::String(s)] = attrs {
+            send(format!("# {}", s));
+            return;
+        }
+
+        let show = match self.searches.front() {
+            Some(Search::Analyze) => true,
+            Some(Search::Play) => self.post,
+            _ => false,
+        };
+
+        if let Some(line) = thinking_line(attrs).filter(|_| show) {
+            send(line);
+        }
+    }
+
+    /// The result to claim if the game is over.
+    fn result(&self) -> Option<&'static str> {
+        let result = match self.position.game_end()? {
+            GameEnd::Checkmate(Color::White) => "1-0 {White mates}",
+            GameEnd::Checkmate(Color::Black) => "0-1 {Black mates}",
+            GameEnd::Stalemate => "1/2-1/2 {Stalemate}",
+            GameEnd::FiftyMoves => "1/2-1/2 {Fifty move rule}",
+            GameEnd::Repetition => "1/2-1/2 {Threefold repetition}",
+            GameEnd::InsufficientMaterial => "1/2-1/2 {Insufficient material}",
+        };
+
+        Some(result)
+    }
+
+    fn time_control(&self) -> UciTimeControl {
+        if let Some(time) = self.move_time {
+            return UciTimeControl::MoveTime(to_uci(time));
+        }
+
+        /* the engine plays whoever is to move */
+        let ours = self.time.unwrap_or(self.level.base);
+        let theirs = self.opponent_time.unwrap_or(self.level.base);
+        let (white_time, black_time) = match self.position.board.side_to_move() {
+            Color::White => (ours, theirs),
+            Color::Black => (theirs, ours),
+        };
+
+        /* the moves left until the next time control */
+        let moves = self.level.moves;
+        let played = self.position.ply as u32 / 2;
+        let moves_to_go = (moves > 0).then(|| (moves - played % moves).min(u8::MAX as u32) as u8);
+
+        UciTimeControl::TimeLeft {
+            white_time: Some(to_uci(white_time)),
+            black_time: Some(to_uci(black_time)),
+            white_increment: Some(to_uci(self.level.increment)),
+            black_increment: Some(to_uci(self.level.increment)),
+            moves_to_go,
+        }
+    }
+
+    fn send_position(&self) {
+        self.send_engine(UciMessage::Position {
+            startpos: self.fen.is_none(),
+            fen: self.fen.clone().map(UciFen),
+            moves: self.moves.clone(),
+        });
+    }
+
+    fn set_option(&self, name: &str, value: &str) {
+        self.send_engine(UciMessage::SetOption {
+            name: name.to_string(),
+            value: Some(value.to_string()),
+        });
+    }
+
+    fn send_engine(&self, message: UciMessage) {
+        let _ = self.tx.send(message.into());
+    }
+}
+
+fn send(line: String) {
+    info!("tx: {}", line);
+    println!("{}", line);
+}
+
+/// Thinking output, `ply score time nodes pv` with the time in centiseconds,
+/// for an info line with a PV.
+fn thinking_line(attrs: &[UciInfoAttribute]) -> Option<String> {
+    let mut depth = 0;
+    let mut score = None;
+    let mut centis = 0;
+    let mut nodes = 0;
+    let mut pv = None;
+
+    for attr in attrs {
+        match attr {
+            UciInfoAttribute::Depth(d) => depth = *d,
+            UciInfoAttribute::Score { cp: Some(cp), .. } => score = Some(*cp),
+            UciInfoAttribute::Score { mate: Some(n), .. } => {
+                let n = *n as i32;
+                score = Some(n.signum() * (MATE_SCORE + n.abs()));
+            }
+            UciInfoAttribute::Time(time) => centis = time.num_milliseconds() / 10,
+            UciInfoAttribute::Nodes(n) => nodes = *n,
+            UciInfoAttribute::Pv(moves) => pv = Some(moves),
+            _ => {}
+        }
+    }
+
+    let pv: Vec<String> = pv?.iter().map(ChessMove::to_string).collect();
+    Some(format!(
+        "{} {} {} {} {}",
+        depth,
+        score?,
+        centis,
+        nodes,
+        pv.join(" ")
+    ))
+}
+
+/* `level 40 5 0` or `level 0 2:30 1.5`: moves, minutes[:seconds] and seconds of increment. */
+fn parse_level(args: &str) -> Option<Level> {
+    let fields: Vec<&str> = args.split_whitespace().collect();
+    let (moves, base, increment) = match fields.as_slice() {
+        [moves, base, increment] => (moves, base, increment),
+        _ => return None,
+    };
+
+    let (minutes, seconds) = base.split_once(':').unwrap_or((base, "0"));
+    let seconds = minutes.parse::<u64>().ok()? * 60 + seconds.parse::<u64>().ok()?;
+
+    Some(Level {
+        moves: moves.parse().ok()?,
+        base: Duration::from_secs(seconds),
+        increment: parse_seconds(increment)?,
+    })
+}
+
+fn parse_seconds(s: &str) -> Option<Duration> {
+    Duration::try_from_secs_f64(s.parse().ok()?).ok()
+}
+
+/* Clocks can go negative when the time runs out. */
+fn parse_centiseconds(s: &str) -> Option<Duration> {
+    let centis = s.parse::<i64>().ok()?;
+    Some(Duration::from_millis(centis.max(0) as u64 * 10))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_level() {
+        assert_eq!(parse_level("40 5 0"), Some(DEFAULT_LEVEL));
+        assert_eq!(
+            parse_level("0 2:30 1.5"),
+            Some(Level {
+                moves: 0,
+                base: Duration::from_secs(150),
+                increment: Duration::from_millis(1500),
+            })
+        );
+        assert_eq!(parse_level("40 5"), None);
+    }
+
+    #[test]
+    fn test_thinking_line() {
+        let e2e4 = ChessMove::from_str("e2e4").unwrap();
+        let e7e5 = ChessMove::from_str("e7e5").unwrap();
+        let mut attrs = vec![
+            UciInfoAttribute::Depth(7),
+            UciInfoAttribute::Score {
+                cp: None,
+                mate: Some(-3),
+                lower_bound: None,
+                upper_bound: None,
+            },
+            UciInfoAttribute::Nodes(12345),
+            UciInfoAttribute::Time(vampirc_uci::Duration::milliseconds(1234)),
+            UciInfoAttribute::Pv(vec![e2e4, e7e5]),
+        ];
+        assert_eq!(
+            thinking_line(&attrs).as_deref(),
+            Some("7 -100003 123 12345 e2e4 e7e5")
+        );
+
+        /* progress reports without a line aren't shown */
+        attrs.pop();
+        assert_eq!(thinking_line(&attrs), None);
+    }
+}
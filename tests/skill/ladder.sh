@@ -0,0 +1,32 @@
+#!/bin/sh
+# Measures every other skill level against the one two below it, appending the
+# games to ladder.txt, then sums the differences up into a rating per level,
+# with level 0 at 0. Set GAMES to change the number of games per match.
+set -e
+root="$(cd "$(dirname "$0")/../.." && pwd)"
+results="$root/tests/skill/ladder.txt"
+games="${GAMES:-40}"
+cargo build --release --manifest-path "$root/Cargo.toml" --bin tune
+
+for level in 0 2 4 6 8 10 12 14 16; do
+    "$root/target/release/tune" skillmatch $((level + 2)) "$level" "$games" "$results"
+done
+
+# <white level> <black level> <result> lines, scored for the higher level
+awk '
+{
+    high = $1 > $2 ? $1 : $2
+    points = $3 == "1/2-1/2" ? 0.5 : ($3 == "1-0") == ($1 == high)
+    score[high] += points
+    games[high]++
+}
+END {
+    elo = 0
+    printf "level 0: 0\n"
+    for (level = 2; level in games; level += 2) {
+        s = score[level] / games[level]
+        s = s < 0.001 ? 0.001 : (s > 0.999 ? 0.999 : s)
+        elo += -400 * log(1 / s - 1) / log(10)
+        printf "level %d: %.0f (%.1f/%d against level %d)\n", level, elo, score[level], games[level], level - 2
+    }
+}' "$results"
@@ -0,0 +1,8 @@
+#!/bin/sh
+# Rebuilds the tablebase fixtures: solves each ending, writes it in the Syzygy
+# format and probes every position back to check the tables.
+set -e
+root="$(cd "$(dirname "$0")/../.." && pwd)"
+cargo build --release --features tablegen --example gentb --manifest-path "$root/Cargo.toml"
+
+"$root/target/release/examples/gentb" "$root/tests/syzygy" KQvK KRvK KPvK KBvK KNvK KNvKN
@@ -0,0 +1,10 @@
+#!/bin/sh
+# Downloads the published KQvK, KRvK and KPvK tables for test_probe_published:
+#   cargo test -- --ignored test_probe_published
+set -e
+cd "$(dirname "$0")"
+for table in KQvK KRvK KPvK; do
+    for ext in rtbw rtbz; do
+        curl -fsSO "https://tablebase.lichess.ovh/tables/standard/3-4-5/$table.$ext"
+    done
+done